@@ -1,12 +1,14 @@
+use crate::error::Error;
 use crate::manifest::{MountPoint, WasmImage};
 
 use std::{
     borrow::Cow,
+    collections::HashMap,
     path::{Path, PathBuf},
     {fs, io},
 };
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use ya_runtime_api::deploy;
@@ -38,11 +40,15 @@ use ya_runtime_api::deploy::ContainerVolume;
 pub struct DeployFile {
     image_path: PathBuf,
     vols: Vec<(bool, deploy::ContainerVolume)>,
+    /// Volume quotas in bytes, keyed by container path.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    quotas: HashMap<String, u64>,
 }
 
 impl DeployFile {
-    fn for_image(image: &WasmImage) -> Result<Self> {
-        let image_path = image.path().to_owned();
+    fn for_image(image_path: &Path, image: &WasmImage) -> Result<Self> {
+        let image_path = image_path.to_owned();
         let convert = |mount_point| {
             (
                 MountPoint::is_private(mount_point),
@@ -54,36 +60,80 @@ impl DeployFile {
         };
 
         let vols = image.manifest.mount_points.iter().map(convert).collect();
+        let quotas = image.manifest.quotas()?;
+        image.manifest.network_targets()?;
 
-        Ok(DeployFile { image_path, vols })
+        Ok(DeployFile {
+            image_path,
+            vols,
+            quotas,
+        })
     }
 
     /// Loads deployed image from workspace where [`ya_runtime_wasi::deploy`] was executed.
     ///
     /// [`ya_runtime_wasi::deploy`]: fn.deploy.html
-    pub fn load(work_dir: impl AsRef<Path>) -> Result<Self> {
+    pub fn load(work_dir: impl AsRef<Path>) -> Result<Self, Error> {
         let deploy_file = deploy_path(work_dir.as_ref());
-        let reader = io::BufReader::new(fs::File::open(&deploy_file).with_context(|| {
+        let reader = io::BufReader::new(fs::File::open(&deploy_file).map_err(Error::image_io(
             format!(
                 "Can't read deploy file {}. Did you run deploy command?",
                 deploy_file.display()
-            )
-        })?);
-        let deploy = serde_json::from_reader(reader)?;
+            ),
+        ))?);
+        let deploy = serde_json::from_reader(reader).map_err(|e| Error::ImageIo {
+            context: format!("Invalid deploy file {}.", deploy_file.display()),
+            source: e.into(),
+        })?;
 
         Ok(deploy)
     }
 
     pub(crate) fn save(&self, work_dir: impl AsRef<Path>) -> Result<()> {
         let deploy_file = deploy_path(work_dir.as_ref());
-        fs::write(&deploy_file, serde_json::to_vec(&self)?)?;
+        fs::write(&deploy_file, serde_json::to_vec(&self)?).map_err(Error::image_io(format!(
+            "Can't write deploy file {}.",
+            deploy_file.display()
+        )))?;
+        Ok(())
+    }
+
+    /// Loads deploy file when the workspace was deployed to.
+    fn load_existing(work_dir: &Path) -> Result<Option<Self>, Error> {
+        if deploy_path(work_dir).exists() {
+            Ok(Some(Self::load(work_dir)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Takes names of `previous` volumes mounted at the same paths.
+    fn reuse_vols(&mut self, previous: &DeployFile) {
+        for (_, vol) in &mut self.vols {
+            if let Some((_, prev)) = previous.vols.iter().find(|(_, v)| v.path == vol.path) {
+                vol.name = prev.name.clone();
+            }
+        }
+    }
+
+    /// Removes volume directories not used by `next` deployment.
+    fn remove_vols_unused_by(&self, work_dir: &Path, next: &DeployFile) -> Result<(), Error> {
+        for (_, vol) in &self.vols {
+            if !next.vols.iter().any(|(_, v)| v.name == vol.name) {
+                remove_vol(work_dir, vol)?;
+            }
+        }
         Ok(())
     }
 
     pub(crate) fn create_dirs(&self, work_dir: impl AsRef<Path>) -> Result<()> {
         let work_dir = work_dir.as_ref();
         for (_, vol) in &self.vols {
-            fs::create_dir(work_dir.join(&vol.name))?;
+            let vol_dir = work_dir.join(&vol.name);
+            fs::create_dir_all(&vol_dir).map_err(Error::image_io(format!(
+                "Can't create volume directory {}.",
+                vol_dir.display()
+            )))?;
         }
         Ok(())
     }
@@ -114,13 +164,29 @@ impl DeployFile {
     pub fn container_vols(&self) -> impl Iterator<Item = &deploy::ContainerVolume> {
         self.vols.iter().map(|(_, v)| v)
     }
+
+    /// Returns quota of the volume in bytes, from the package manifest.
+    pub fn quota(&self, vol: &deploy::ContainerVolume) -> Option<u64> {
+        self.quotas.get(&vol.path).copied()
+    }
 }
 
 fn deploy_path(work_dir: &Path) -> PathBuf {
     work_dir.join("deploy.json")
 }
 
-fn absolute_path(path: &str) -> Cow<'_, str> {
+pub(crate) fn remove_vol(work_dir: &Path, vol: &ContainerVolume) -> Result<(), Error> {
+    let vol_dir = work_dir.join(&vol.name);
+    match fs::remove_dir_all(&vol_dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::image_io(format!(
+            "Can't remove volume directory {}.",
+            vol_dir.display()
+        ))(e)),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn absolute_path(path: &str) -> Cow<'_, str> {
     if path.starts_with('/') {
         Cow::Borrowed(path)
     } else {
@@ -128,9 +194,59 @@ fn absolute_path(path: &str) -> Cow<'_, str> {
     }
 }
 
+/// Deployment configuration.
+#[derive(Default, Clone, Debug)]
+pub struct DeployOptions {
+    keep_volumes: bool,
+}
+
+impl DeployOptions {
+    /// Reuses volumes of the previous deployment mounted at the same paths,
+    /// instead of replacing them with empty ones.
+    pub fn with_keep_volumes(mut self, keep_volumes: bool) -> Self {
+        self.keep_volumes = keep_volumes;
+        self
+    }
+
+    /// Deploys the Wasm image into the workspace, replacing previous deployment.
+    ///
+    /// Volumes of the previous deployment which are not reused are removed.
+    pub fn deploy(
+        self,
+        workdir: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+    ) -> Result<deploy::DeployResult, Error> {
+        let workdir = workdir.as_ref();
+        let path = path.as_ref();
+
+        let image = WasmImage::new(&path)?;
+        let mut deploy_file = DeployFile::for_image(path, &image)?;
+        if let Some(previous) = DeployFile::load_existing(workdir)? {
+            if self.keep_volumes {
+                deploy_file.reuse_vols(&previous);
+            }
+            previous.remove_vols_unused_by(workdir, &deploy_file)?;
+        }
+        deploy_file.save(workdir)?;
+        deploy_file.create_dirs(workdir)?;
+
+        let res = deploy::DeployResult {
+            valid: Ok(Default::default()),
+            vols: deploy_file.public_vols().collect(),
+            start_mode: Default::default(),
+        };
+
+        Ok(res)
+    }
+}
+
 /// Deploys the Wasm image into the workspace.
 ///
-/// Takes path to workdir and path to the Wasm image as arguments.
+/// Takes path to workdir and path to the Wasm image as arguments. Deploying into
+/// the same workspace again replaces the previous deployment, see [`DeployOptions`]
+/// to keep its volumes.
+///
+/// [`DeployOptions`]: struct.DeployOptions.html
 ///
 /// ## Example
 ///
@@ -140,21 +256,80 @@ fn absolute_path(path: &str) -> Cow<'_, str> {
 ///
 /// deploy(Path::new("workspace"), Path::new("package.zig")).unwrap();
 /// ```
-pub fn deploy(workdir: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<deploy::DeployResult> {
+pub fn deploy(
+    workdir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> Result<deploy::DeployResult, Error> {
+    DeployOptions::default().deploy(workdir, path)
+}
+
+/// Removes volumes and the deploy file from the workspace.
+///
+/// Succeeds when the workspace is not deployed. Compiled modules are kept in the
+/// cache directory, which is shared by all workspaces and evicted by wasmtime.
+pub fn undeploy(workdir: impl AsRef<Path>) -> Result<(), Error> {
     let workdir = workdir.as_ref();
-    let path = path.as_ref();
+    if let Some(deploy_file) = DeployFile::load_existing(workdir)? {
+        for (_, vol) in &deploy_file.vols {
+            remove_vol(workdir, vol)?;
+        }
+        let path = deploy_path(workdir);
+        fs::remove_file(&path).map_err(Error::image_io(format!(
+            "Can't remove deploy file {}.",
+            path.display()
+        )))?;
+    }
+    Ok(())
+}
 
-    let image = WasmImage::new(&path)
-        .with_context(|| format!("Can't read image file {}.", path.display()))?;
-    let deploy_file = DeployFile::for_image(&image)?;
-    deploy_file.save(workdir)?;
-    deploy_file.create_dirs(workdir)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
 
-    let res = deploy::DeployResult {
-        valid: Ok(Default::default()),
-        vols: deploy_file.public_vols().collect(),
-        start_mode: Default::default(),
-    };
+    fn write_package(path: &Path, mount_points: serde_json::Value) {
+        let manifest = serde_json::json!({ "mount-points": mount_points });
+        fs::write(path, testing::package("(module)", manifest)).unwrap();
+    }
+
+    fn vol_names(work_dir: &Path) -> Vec<String> {
+        let deploy_file = DeployFile::load(work_dir).unwrap();
+        deploy_file
+            .container_vols()
+            .map(|vol| vol.name.clone())
+            .collect()
+    }
 
-    Ok(res)
+    #[test]
+    fn test_redeploy() {
+        let dir = tempfile::tempdir().unwrap();
+        let work_dir = dir.path().join("work");
+        fs::create_dir(&work_dir).unwrap();
+        let package = dir.path().join("package.zip");
+        write_package(
+            &package,
+            serde_json::json!([{"rw": "/data"}, {"private": "/keys"}]),
+        );
+
+        deploy(&work_dir, &package).unwrap();
+        let first = vol_names(&work_dir);
+        let keep = DeployOptions::default().with_keep_volumes(true);
+        keep.clone().deploy(&work_dir, &package).unwrap();
+        assert_eq!(vol_names(&work_dir), first);
+
+        write_package(&package, serde_json::json!([{"rw": "/data"}]));
+        keep.deploy(&work_dir, &package).unwrap();
+        assert_eq!(vol_names(&work_dir), first[..1]);
+        assert!(!work_dir.join(&first[1]).exists());
+
+        deploy(&work_dir, &package).unwrap();
+        let replaced = vol_names(&work_dir);
+        assert_ne!(replaced, first[..1]);
+        assert!(!work_dir.join(&first[0]).exists());
+        assert!(work_dir.join(&replaced[0]).is_dir());
+
+        undeploy(&work_dir).unwrap();
+        assert_eq!(fs::read_dir(&work_dir).unwrap().count(), 0);
+        undeploy(&work_dir).unwrap();
+    }
 }
@@ -1,14 +1,30 @@
+use crate::error::Error;
+use crate::handle::{Cancel, RunHandle};
+use crate::network::{Network, NetworkUsage};
+use crate::output::{OutputHandler, SharedOutputHandler};
+use crate::package::Package;
+use crate::quota::VolumeUsage;
 use crate::{deploy::DeployFile, manifest::WasmImage, wasmtime_unit::Wasmtime};
 
 use std::env;
+use std::io::{Read, Seek};
 use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, Result};
+use futures::channel::oneshot;
 use log::info;
+use serde::Serialize;
+use ya_runtime_common::parse_size;
 
 const INIT_MEM_VAR: &str = "YA_RUNTIME_WASI_INIT_MEM";
 const OPTIMIZE_VAR: &str = "YA_RUNTIME_WASI_OPT";
 const SGX_VAR: &str = "YA_RUNTIME_WASI_SGX";
+const TIMEOUT_VAR: &str = "YA_RUNTIME_WASI_TIMEOUT";
+const CACHE_DIR_VAR: &str = "YA_RUNTIME_WASI_CACHE_DIR";
+const MAX_VOLUME_SIZE_VAR: &str = "YA_RUNTIME_WASI_MAX_VOLUME_SIZE";
+const NET_ALLOW_VAR: &str = "YA_RUNTIME_WASI_NET_ALLOW";
 
 /// WASI runtime configuration.
 #[derive(Default, Clone, Debug)]
@@ -16,6 +32,12 @@ pub struct RuntimeOptions {
     pub(crate) max_static_memory: Option<u64>,
     pub(crate) optimize: Option<bool>,
     pub(crate) sgx_profile: Option<bool>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) cache_dir: Option<PathBuf>,
+    pub(crate) max_volume_size: Option<u64>,
+    pub(crate) network_allowlist: Vec<String>,
+    pub(crate) output_handler: Option<SharedOutputHandler>,
+    pub(crate) cancel: Option<Cancel>,
 }
 
 impl RuntimeOptions {
@@ -24,28 +46,27 @@ impl RuntimeOptions {
     /// * `YA_RUNTIME_WASI_INIT_MEM` - maximum memory size. (supported formats 250m, 1.2g)
     /// * `YA_RUNTIME_WASI_OPT` - optimization. (0|no for no optimalization), (1|yes)
     /// * `YA_RUNTIME_WASI_SGX` - enables sgx profiled configuration.
+    /// * `YA_RUNTIME_WASI_TIMEOUT` - maximum execution time of a single run, in seconds.
+    /// * `YA_RUNTIME_WASI_CACHE_DIR` - directory for compiled modules cache.
+    /// * `YA_RUNTIME_WASI_MAX_VOLUME_SIZE` - maximum size of files in a volume.
+    /// * `YA_RUNTIME_WASI_NET_ALLOW` - comma separated `host:port` endpoints guests may connect to.
     ///
-    pub fn from_env() -> Result<Self> {
-        let mut me = Self::default();
-
-        if let Some(err_msg) = (|| {
-            let mem_str = env::var(INIT_MEM_VAR).ok()?;
-            let len = mem_str.as_bytes().len();
-            let scale = match mem_str.as_bytes().get(len - 1) {
-                Some(b'k') => 0x400,
-                Some(b'm') => 0x100_000,
-                Some(b'g') => 0x40_000_000,
-                _ => return Some(format!("invalid max mem spec: {}", mem_str)),
-            };
-            let value = match mem_str[..len - 1].parse::<u64>() {
-                Ok(val) => val,
-                Err(e) => return Some(format!("invalid max mem spec: {} ({})", mem_str, e)),
-            };
-            me.max_static_memory = Some(value * scale);
-            None
-        })() {
-            log::warn!("wasi env MAX_MEM_VAR {}", err_msg);
-            return Err(anyhow::Error::msg(err_msg));
+    pub fn from_env() -> Result<Self, Error> {
+        Self::default().with_env()
+    }
+
+    /// Overrides options with the ones set in environment variables, see [`from_env`].
+    ///
+    /// [`from_env`]: #method.from_env
+    pub fn with_env(mut self) -> Result<Self, Error> {
+        if let Ok(mem_str) = env::var(INIT_MEM_VAR) {
+            match parse_size(&mem_str) {
+                Ok(max_static_memory) => self.max_static_memory = Some(max_static_memory),
+                Err(e) => {
+                    log::warn!("wasi env {} {}", INIT_MEM_VAR, e);
+                    return Err(e.into());
+                }
+            }
         }
 
         fn parse_bool(env_var: &str) -> Result<Option<bool>> {
@@ -60,9 +81,46 @@ impl RuntimeOptions {
                 Err(_) => Ok(None),
             }
         }
-        me.optimize = parse_bool(OPTIMIZE_VAR)?;
-        me.sgx_profile = parse_bool(SGX_VAR)?;
-        Ok(me)
+        if let Some(optimize) = parse_bool(OPTIMIZE_VAR)? {
+            self.optimize = Some(optimize);
+        }
+        if let Some(sgx_profile) = parse_bool(SGX_VAR)? {
+            self.sgx_profile = Some(sgx_profile);
+        }
+        if let Ok(timeout) = env::var(TIMEOUT_VAR) {
+            let secs = timeout.trim().parse::<u64>().map_err(|e| {
+                Error::Other(anyhow!(
+                    "invalid value ({}) for {}: {}",
+                    timeout,
+                    TIMEOUT_VAR,
+                    e
+                ))
+            })?;
+            self.timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(cache_dir) = env::var_os(CACHE_DIR_VAR) {
+            self.cache_dir = Some(cache_dir.into());
+        }
+        if let Ok(size) = env::var(MAX_VOLUME_SIZE_VAR) {
+            let max_volume_size = parse_size(&size).map_err(|e| {
+                Error::Other(anyhow!(
+                    "invalid value ({}) for {}: {}",
+                    size,
+                    MAX_VOLUME_SIZE_VAR,
+                    e
+                ))
+            })?;
+            self.max_volume_size = Some(max_volume_size);
+        }
+        if let Ok(allowlist) = env::var(NET_ALLOW_VAR) {
+            self.network_allowlist = allowlist
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_owned)
+                .collect();
+        }
+        Ok(self)
     }
 
     /// Configures the maximum size, in bytes, where a linear memory is
@@ -89,8 +147,47 @@ impl RuntimeOptions {
         self
     }
 
+    /// Limits execution time of a single run, the guest is interrupted when exceeded.
+    pub fn with_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// Caches compiled modules in the given directory.
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<Option<PathBuf>>) -> Self {
+        self.cache_dir = cache_dir.into();
+        self
+    }
+
+    /// Limits size of files in each volume, lowers quotas set in the package manifest.
+    pub fn with_max_volume_size(mut self, max_volume_size: impl Into<Option<u64>>) -> Self {
+        self.max_volume_size = max_volume_size.into();
+        self
+    }
+
+    /// Allows guests to connect to the `host:port` endpoints when declared in the manifest.
+    pub fn with_network_allowlist(
+        mut self,
+        allowlist: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.network_allowlist = allowlist.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Delivers guest stdout and stderr to `handler` instead of inheriting process stdio.
+    pub fn with_output_handler(mut self, handler: impl OutputHandler + 'static) -> Self {
+        self.output_handler = Some(SharedOutputHandler(Arc::new(handler)));
+        self
+    }
+
     pub(crate) fn is_default(&self) -> bool {
-        self.max_static_memory.is_none() && self.optimize.is_none() && self.sgx_profile.is_none()
+        self.max_static_memory.is_none()
+            && self.optimize.is_none()
+            && self.sgx_profile.is_none()
+            && self.timeout.is_none()
+            && self.cache_dir.is_none()
+            && self.max_volume_size.is_none()
+            && self.cancel.is_none()
     }
 
     /// Instantiates and executes the deployed image using Wasmtime runtime.
@@ -99,12 +196,12 @@ impl RuntimeOptions {
         workdir: impl AsRef<Path>,
         entrypoint: impl AsRef<str>,
         args: impl IntoIterator<Item = String>,
-    ) -> Result<()> {
+    ) -> Result<RunOutcome, Error> {
         let workdir = workdir.as_ref();
         let deploy_file = DeployFile::load(workdir)?;
 
         let mut image = WasmImage::new(&deploy_file.image_path())?;
-        let mut wasmtime = create_wasmtime(workdir, &deploy_file, self)?;
+        let mut wasmtime = create_wasmtime(workdir, &deploy_file, &image, self)?;
 
         info!(
             "Running image: {:?}",
@@ -116,15 +213,87 @@ impl RuntimeOptions {
         // we must deploy image for the second time, what will load binary to wasmtime.
         let entrypoint = image.find_entrypoint(entrypoint.as_ref())?;
         wasmtime.load_binary(&mut image, &entrypoint)?;
-        wasmtime.run(entrypoint, args.into_iter().collect())?;
+        let result = wasmtime
+            .run(entrypoint, args.into_iter().collect())
+            .and_then(|_| wasmtime.outcome());
+        // Waits until the guest output is delivered to the handler.
+        wasmtime.finish();
+        let outcome = result?;
 
         info!("Computations completed.");
 
-        Ok(())
+        Ok(outcome)
+    }
+
+    /// Instantiates and executes entrypoint of a package loaded in memory.
+    ///
+    /// `host_dirs` map every package mount point, by its guest path, to a host directory.
+    pub fn run_package<R: Read + Seek>(
+        self,
+        package: &mut Package<R>,
+        host_dirs: impl IntoIterator<Item = (String, PathBuf)>,
+        entrypoint: impl AsRef<str>,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<RunOutcome, Error> {
+        let mounts = package.mounts(host_dirs)?;
+        let network = Network::new(
+            package.image.manifest.network_targets()?,
+            &self.network_allowlist,
+        )?;
+        let mut wasmtime = Wasmtime::new(mounts, network, self)?;
+
+        info!("Running package: {}", package.image.manifest.name);
+
+        let entrypoint = package.image.find_entrypoint(entrypoint.as_ref())?;
+        wasmtime.load_binary(&mut package.image, &entrypoint)?;
+        let result = wasmtime
+            .run(entrypoint, args.into_iter().collect())
+            .and_then(|_| wasmtime.outcome());
+        wasmtime.finish();
+        let outcome = result?;
+
+        info!("Computations completed.");
+
+        Ok(outcome)
+    }
+
+    /// Runs the deployed image in a background thread.
+    ///
+    /// The returned handle resolves to the run result and allows to cancel the run.
+    pub fn spawn(
+        mut self,
+        workdir: impl AsRef<Path>,
+        entrypoint: impl AsRef<str>,
+        args: impl IntoIterator<Item = String>,
+    ) -> RunHandle {
+        let workdir = workdir.as_ref().to_owned();
+        let entrypoint = entrypoint.as_ref().to_owned();
+        let args = args.into_iter().collect::<Vec<_>>();
+        let cancel = Cancel::default();
+        self.cancel = Some(cancel.clone());
+
+        let (result_tx, result_rx) = oneshot::channel();
+        let spawned = std::thread::Builder::new()
+            .name(format!("wasi-{}", entrypoint))
+            .spawn(move || {
+                let _ = result_tx.send(self.run(workdir, entrypoint, args));
+            });
+        let result_rx = match spawned {
+            Ok(_) => result_rx,
+            Err(e) => {
+                let (result_tx, result_rx) = oneshot::channel();
+                let _ = result_tx.send(Err(Error::Other(anyhow!(
+                    "Failed to spawn runtime thread: {}",
+                    e
+                ))));
+                result_rx
+            }
+        };
+        RunHandle::new(result_rx, cancel)
     }
 
     /// Validates the deployed image.
-    pub fn start(self, workdir: impl AsRef<Path>) -> Result<()> {
+    pub fn start(self, workdir: impl AsRef<Path>) -> Result<(), Error> {
         let workdir = workdir.as_ref();
         let deploy_file = DeployFile::load(workdir)?;
 
@@ -134,7 +303,7 @@ impl RuntimeOptions {
         );
 
         let mut image = WasmImage::new(&deploy_file.image_path())?;
-        let mut wasmtime = create_wasmtime(workdir, &deploy_file, self)?;
+        let mut wasmtime = create_wasmtime(workdir, &deploy_file, &image, self)?;
 
         wasmtime.load_binaries(&mut image)?;
 
@@ -147,7 +316,7 @@ impl RuntimeOptions {
 /// Validates the deployed image.
 ///
 /// Takes path to the workdir as an argument.
-pub fn start(workdir: impl AsRef<Path>) -> Result<()> {
+pub fn start(workdir: impl AsRef<Path>) -> Result<(), Error> {
     RuntimeOptions::default().start(workdir)
 }
 
@@ -174,18 +343,31 @@ pub fn run(
     workdir: impl AsRef<Path>,
     entrypoint: impl AsRef<str>,
     args: impl IntoIterator<Item = String>,
-) -> Result<()> {
+) -> Result<RunOutcome, Error> {
     RuntimeOptions::default().run(workdir, entrypoint, args)
 }
 
+/// Result of a completed run.
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RunOutcome {
+    /// Disk usage of the mounted volumes.
+    pub volumes: Vec<VolumeUsage>,
+    /// Network traffic of the guest.
+    pub network: NetworkUsage,
+}
+
 pub(crate) struct DirectoryMount {
     pub host: PathBuf,
     pub guest: PathBuf,
+    /// Quota from the package manifest, in bytes.
+    pub quota: Option<u64>,
 }
 
 fn create_wasmtime(
     workdir: &Path,
     deploy: &DeployFile,
+    image: &WasmImage,
     options: RuntimeOptions,
 ) -> Result<Wasmtime> {
     let mounts = deploy
@@ -194,25 +376,44 @@ fn create_wasmtime(
             let host = workdir.join(&v.name);
             let guest = PathBuf::from(&v.path);
             validate_mount_path(&guest)?;
-            Ok(DirectoryMount { host, guest })
+            Ok(DirectoryMount {
+                host,
+                guest,
+                quota: deploy.quota(v),
+            })
         })
         .collect::<anyhow::Result<Vec<_>>>()?;
-    Ok(Wasmtime::new(mounts, options))
+    let network = Network::new(
+        image.manifest.network_targets()?,
+        &options.network_allowlist,
+    )?;
+    Wasmtime::new(mounts, network, options)
 }
 
-fn validate_mount_path(path: &Path) -> Result<()> {
+pub(crate) fn validate_mount_path(path: &Path) -> Result<(), Error> {
     // Protect ExeUnit from directory traversal attack.
     // Wasm can access only paths inside working directory.
     let path = PathBuf::from(path);
     for component in path.components() {
         match component {
             Component::Prefix { .. } => {
-                bail!("Expected unix path instead of [{}].", path.display())
+                return Err(Error::MountRejected(format!(
+                    "Expected unix path instead of [{}].",
+                    path.display()
+                )))
             }
             Component::ParentDir { .. } => {
-                bail!("Path [{}] contains illegal '..' component.", path.display())
+                return Err(Error::MountRejected(format!(
+                    "Path [{}] contains illegal '..' component.",
+                    path.display()
+                )))
+            }
+            Component::CurDir => {
+                return Err(Error::MountRejected(format!(
+                    "Path [{}] contains illegal '.' component.",
+                    path.display()
+                )))
             }
-            Component::CurDir => bail!("Path [{}] contains illegal '.' component.", path.display()),
             _ => (),
         }
     }
@@ -232,6 +433,7 @@ fn get_log_path<'a>(workdir: &'a Path, path: &'a Path) -> &'a Path {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing;
 
     #[test]
     fn test_mount_path_validation() {
@@ -253,6 +455,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_quota_check_fails_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let host = dir.path().join("data");
+        std::fs::create_dir(&host).unwrap();
+        let manifest = serde_json::json!({
+            "mount-points": [{"rw": "data"}],
+            "quotas": {"data": 1024},
+        });
+        let mut package = Package::from_bytes(testing::package(testing::SPIN, manifest)).unwrap();
+
+        // usage of a removed volume cannot be checked
+        let remove = {
+            let host = host.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                std::fs::remove_dir(host).unwrap();
+            })
+        };
+        let result = RuntimeOptions::default().run_package(
+            &mut package,
+            vec![("/data".to_string(), host)],
+            "main",
+            Vec::new(),
+        );
+        remove.join().unwrap();
+        assert!(matches!(result, Err(Error::LimitExceeded { .. })));
+    }
+
     #[test]
     fn test_options() {
         env::set_var(INIT_MEM_VAR, "250m");
@@ -0,0 +1,127 @@
+use crate::network::NetworkUsage;
+use std::io;
+
+/// Error returned by the runtime API.
+///
+/// Converts to `anyhow::Error`, so it can be propagated with `?` from functions
+/// returning `anyhow::Result`.
+///
+/// New variants may be added, matches have to include a wildcard arm.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Package is not a valid zip archive, or its manifest is missing or malformed.
+    #[error("Invalid package")]
+    ManifestInvalid(#[source] anyhow::Error),
+    /// Package has no entrypoint with the given id.
+    #[error("Entrypoint {0} not found.")]
+    EntrypointNotFound(String),
+    /// Reading the image or the deploy file, or setting up the workdir failed.
+    #[error("{context}")]
+    ImageIo {
+        /// What was being done.
+        context: String,
+        /// Underlying i/o error.
+        #[source]
+        source: io::Error,
+    },
+    /// Wasm binary failed to compile.
+    #[error("Failed to create Wasm module for binary: '{module}'")]
+    Compile {
+        /// Entrypoint id.
+        module: String,
+        /// Compilation error.
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Module failed to instantiate, or has no `_start` export.
+    #[error("Failed to instantiate module: '{module}'")]
+    Link {
+        /// Entrypoint id.
+        module: String,
+        /// Link error.
+        #[source]
+        source: anyhow::Error,
+    },
+    /// Guest trapped or exited with non-zero status.
+    #[error("Failed to run module: '{module}', exit_code={exit_code}")]
+    Trap {
+        /// Entrypoint id.
+        module: String,
+        /// Guest exit status, or the status of an aborted process when the guest trapped.
+        exit_code: i32,
+        /// Network traffic of the guest until it stopped.
+        network: NetworkUsage,
+        /// Trap raised by the guest.
+        #[source]
+        source: wasmtime::Trap,
+    },
+    /// Volume can not be mounted at the requested guest path.
+    #[error("{0}")]
+    MountRejected(String),
+    /// Package declares network target not allowed by the provider.
+    #[error("{0}")]
+    NetworkRejected(String),
+    /// Guest exceeded execution time limit or a volume quota.
+    #[error("Execution limit exceeded")]
+    LimitExceeded {
+        /// Network traffic of the guest until it was interrupted.
+        network: NetworkUsage,
+        /// Exceeded limit, of `TimedOut` kind when the execution timed out.
+        #[source]
+        source: io::Error,
+    },
+    /// Run was cancelled through its [`RunHandle`], or the handle was dropped.
+    ///
+    /// [`RunHandle`]: struct.RunHandle.html
+    #[error("Execution cancelled")]
+    Cancelled {
+        /// Network traffic of the guest until it was cancelled.
+        network: NetworkUsage,
+    },
+    /// Any other failure.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Network traffic of the guest before a failed run stopped.
+    pub fn network_usage(&self) -> Option<NetworkUsage> {
+        match self {
+            Error::Trap { network, .. }
+            | Error::LimitExceeded { network, .. }
+            | Error::Cancelled { network } => Some(*network),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn image_io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| Error::ImageIo { context, source }
+    }
+}
+
+/// Recovers error raised by the runtime from the `anyhow` chain, other errors are
+/// kept as [`Error::Other`].
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<Error>() {
+            Ok(err) => err,
+            Err(err) => Error::Other(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_anyhow() {
+        let err = anyhow::Error::new(Error::EntrypointNotFound("hello".into())).context("running");
+        assert!(matches!(Error::from(err), Error::EntrypointNotFound(id) if id == "hello"));
+
+        let err = anyhow::anyhow!("other");
+        assert!(matches!(Error::from(err), Error::Other(_)));
+    }
+}
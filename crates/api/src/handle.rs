@@ -0,0 +1,181 @@
+use crate::entrypoint::RunOutcome;
+use crate::error::Error;
+use futures::channel::oneshot;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use wasmtime::InterruptHandle;
+
+/// Result of a spawned run.
+///
+/// A cancelled run fails with [`Error::Cancelled`].
+pub type RunResult = Result<RunOutcome, Error>;
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: bool,
+    interrupt: Option<InterruptHandle>,
+}
+
+/// Interrupts the guest on cancel, also when cancelled before the guest started.
+#[derive(Clone, Default)]
+pub(crate) struct Cancel(Arc<Mutex<CancelState>>);
+
+impl Cancel {
+    pub fn cancel(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.cancelled = true;
+        if let Some(interrupt) = &state.interrupt {
+            interrupt.interrupt();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    pub fn register(&self, interrupt: InterruptHandle) {
+        let mut state = self.0.lock().unwrap();
+        if state.cancelled {
+            interrupt.interrupt();
+        }
+        state.interrupt = Some(interrupt);
+    }
+}
+
+impl fmt::Debug for Cancel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cancel")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Handle to a run started by [`RuntimeOptions::spawn`].
+///
+/// Resolves to the run result. Dropping the handle aborts the guest.
+///
+/// [`RuntimeOptions::spawn`]: struct.RuntimeOptions.html#method.spawn
+pub struct RunHandle {
+    result: oneshot::Receiver<RunResult>,
+    cancel: Cancel,
+}
+
+impl RunHandle {
+    pub(crate) fn new(result: oneshot::Receiver<RunResult>, cancel: Cancel) -> Self {
+        RunHandle { result, cancel }
+    }
+
+    /// Interrupts the guest, the handle resolves once the guest is stopped.
+    pub fn cancel(&self) {
+        self.cancel.cancel()
+    }
+}
+
+impl Future for RunHandle {
+    type Output = RunResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.result).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(_)) => {
+                Poll::Ready(Err(Error::Other(anyhow::anyhow!("Runtime thread crashed"))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for RunHandle {
+    fn drop(&mut self) {
+        self.cancel.cancel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deploy, testing, OutputHandler, RuntimeOptions};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Reports when the runtime releases it, which happens after the guest stopped.
+    struct Released(Mutex<mpsc::Sender<()>>);
+
+    impl OutputHandler for Released {
+        fn handle_stdout(&self, _: &[u8]) {}
+
+        fn handle_stderr(&self, _: &[u8]) {}
+    }
+
+    impl Drop for Released {
+        fn drop(&mut self) {
+            let _ = self.0.lock().unwrap().send(());
+        }
+    }
+
+    /// Spawns a guest spinning forever, the receiver is notified when it stops.
+    fn spawn_spin(workdir: &TempDir) -> (RunHandle, mpsc::Receiver<()>) {
+        let package = workdir.path().join("spin.zip");
+        std::fs::write(
+            &package,
+            testing::package(testing::SPIN, serde_json::json!({})),
+        )
+        .unwrap();
+        deploy(workdir.path(), &package).unwrap();
+
+        let (released_tx, released_rx) = mpsc::channel();
+        let handle = RuntimeOptions::default()
+            .with_output_handler(Released(Mutex::new(released_tx)))
+            .spawn(workdir.path(), "main", Vec::new());
+        // the guest is still spinning
+        assert!(released_rx
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+        (handle, released_rx)
+    }
+
+    #[test]
+    fn test_cancel() {
+        let workdir = tempfile::tempdir().unwrap();
+        let (handle, released) = spawn_spin(&workdir);
+
+        handle.cancel();
+        released.recv_timeout(STOP_TIMEOUT).unwrap();
+        assert!(matches!(
+            futures::executor::block_on(handle),
+            Err(Error::Cancelled { .. })
+        ));
+    }
+
+    #[test]
+    fn test_drop() {
+        let workdir = tempfile::tempdir().unwrap();
+        let (handle, released) = spawn_spin(&workdir);
+
+        drop(handle);
+        released.recv_timeout(STOP_TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn test_cancel_before_register() {
+        let mut config = wasmtime::Config::new();
+        config.interruptable(true);
+        let store = wasmtime::Store::new(&wasmtime::Engine::new(&config));
+        let module =
+            wasmtime::Module::new(store.engine(), wat::parse_str(testing::SPIN).unwrap()).unwrap();
+        let instance = wasmtime::Instance::new(&store, &module, &[]).unwrap();
+        let start = instance.get_func("_start").unwrap().get0::<()>().unwrap();
+
+        let cancel = Cancel::default();
+        cancel.cancel();
+        cancel.register(store.interrupt_handle().unwrap());
+        assert!(cancel.is_cancelled());
+        assert!(start().is_err());
+    }
+}
@@ -10,7 +10,7 @@
 //! ```toml
 //! # Cargo.toml
 //! [dependencies]
-//! ya-runtime-wasi = "0.2"
+//! ya-runtime-wasi = "0.3"
 //! ```
 //!
 //! You can now embed the runtime in your app like so
@@ -53,6 +53,66 @@
 //! [`ya_runtime_wasi::deploy`]: fn.deploy.html
 //! [`ya_runtime_wasi::start`]: fn.start.html
 //!
+//! ## Running packages from memory
+//!
+//! Packages received over the network can be run without writing them to disk. Load one
+//! with [`Package::from_bytes`] or [`Package::from_reader`], and run it with
+//! [`RuntimeOptions::run_package`], mapping package mount points to host directories.
+//!
+//! [`Package::from_bytes`]: struct.Package.html#method.from_bytes
+//! [`Package::from_reader`]: struct.Package.html#method.from_reader
+//! [`RuntimeOptions::run_package`]: struct.RuntimeOptions.html#method.run_package
+//!
+//! ## Running in background
+//!
+//! [`RuntimeOptions::spawn`] runs the image in a separate thread and returns a [`RunHandle`]
+//! future resolving to the run result. The run is interrupted by [`RunHandle::cancel`],
+//! or when the handle is dropped.
+//!
+//! [`RuntimeOptions::spawn`]: struct.RuntimeOptions.html#method.spawn
+//! [`RunHandle`]: struct.RunHandle.html
+//! [`RunHandle::cancel`]: struct.RunHandle.html#method.cancel
+//!
+//! ## Errors
+//!
+//! Functions of the API fail with [`Error`], which variants allow to tell an invalid
+//! package from a guest trap or a rejected mount without matching error messages.
+//!
+//! [`Error`]: enum.Error.html
+//!
+//! ## Capturing output
+//!
+//! By default the guest inherits stdio of the embedding process. Guest stdout and stderr
+//! can be received while the guest runs by passing an [`OutputHandler`] to
+//! [`RuntimeOptions::with_output_handler`].
+//!
+//! [`OutputHandler`]: trait.OutputHandler.html
+//! [`RuntimeOptions::with_output_handler`]: struct.RuntimeOptions.html#method.with_output_handler
+//!
+//! ## Disk quotas
+//!
+//! Package manifest may limit size of files in its volumes with `quotas`, keyed by mount
+//! point path, and [`RuntimeOptions::with_max_volume_size`] caps them. A run writing over
+//! a quota is interrupted with [`Error::LimitExceeded`]. Usage of every volume is reported
+//! in [`RunOutcome`].
+//!
+//! [`RuntimeOptions::with_max_volume_size`]: struct.RuntimeOptions.html#method.with_max_volume_size
+//! [`Error::LimitExceeded`]: enum.Error.html#variant.LimitExceeded
+//! [`RunOutcome`]: struct.RunOutcome.html
+//!
+//! ## Network access
+//!
+//! Guests have no network access by default. Package manifest may declare `network`
+//! endpoints in `host:port` form, which the guest connects to with functions imported
+//! from the `ya_net` module. Every declared endpoint has to be allowed by the provider
+//! with [`RuntimeOptions::with_network_allowlist`], otherwise the run fails with
+//! [`Error::NetworkRejected`]. Traffic of the guest is reported in [`RunOutcome`], and by
+//! [`Error::network_usage`] when the guest traps, times out or is cancelled.
+//!
+//! [`RuntimeOptions::with_network_allowlist`]: struct.RuntimeOptions.html#method.with_network_allowlist
+//! [`Error::NetworkRejected`]: enum.Error.html#variant.NetworkRejected
+//! [`Error::network_usage`]: enum.Error.html#method.network_usage
+//!
 //! ## Examples
 //!
 //! A good example of using `ya-runtime-wasi` embedding API can be found in the [`gfaas`]
@@ -64,8 +124,22 @@
 
 mod deploy;
 mod entrypoint;
+mod error;
+mod handle;
 mod manifest;
+mod network;
+mod output;
+mod package;
+mod quota;
+#[cfg(test)]
+mod testing;
 mod wasmtime_unit;
 
-pub use deploy::{deploy, DeployFile};
-pub use entrypoint::{run, start, RuntimeOptions};
+pub use deploy::{deploy, undeploy, DeployFile, DeployOptions};
+pub use entrypoint::{run, start, RunOutcome, RuntimeOptions};
+pub use error::Error;
+pub use handle::{RunHandle, RunResult};
+pub use network::NetworkUsage;
+pub use output::OutputHandler;
+pub use package::Package;
+pub use quota::VolumeUsage;
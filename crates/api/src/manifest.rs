@@ -1,8 +1,13 @@
-use anyhow::{anyhow, Context, Result};
+use crate::deploy::absolute_path;
+use crate::error::Error;
+use crate::network::Target;
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
-use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::io::{Read, Seek};
+use std::path::Path;
+use ya_runtime_common::Size;
 use zip::ZipArchive;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -19,6 +24,48 @@ pub(crate) struct Manifest {
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub mount_points: Vec<MountPoint>,
+
+    /// Disk quotas keyed by mount point path.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub quotas: HashMap<String, Size>,
+
+    /// Endpoints in `host:port` form the guest connects to.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub network: Vec<String>,
+}
+
+impl Manifest {
+    /// Returns quotas in bytes, keyed by absolute mount point path.
+    pub fn quotas(&self) -> Result<HashMap<String, u64>, Error> {
+        let mut quotas = HashMap::new();
+        for (path, size) in &self.quotas {
+            let path = absolute_path(path).into_owned();
+            if !self
+                .mount_points
+                .iter()
+                .any(|mount_point| absolute_path(mount_point.path()) == path)
+            {
+                return Err(Error::ManifestInvalid(anyhow!(
+                    "Quota for unknown mount point [{}].",
+                    path
+                )));
+            }
+            let bytes = size.bytes().map_err(Error::ManifestInvalid)?;
+            quotas.insert(path, bytes);
+        }
+        Ok(quotas)
+    }
+
+    /// Returns parsed network targets.
+    pub fn network_targets(&self) -> Result<Vec<Target>, Error> {
+        self.network
+            .iter()
+            .map(|target| target.parse())
+            .collect::<Result<_>>()
+            .map_err(Error::ManifestInvalid)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Eq, PartialEq)]
@@ -55,25 +102,34 @@ impl MountPoint {
     }
 }
 
-pub(crate) struct WasmImage {
-    archive: ZipArchive<File>,
+pub(crate) struct WasmImage<R = File> {
+    archive: ZipArchive<R>,
     pub manifest: Manifest,
-    image_path: PathBuf,
 }
 
 impl WasmImage {
     pub fn new(image_path: &Path) -> Result<Self> {
-        let mut archive = zip::ZipArchive::new(OpenOptions::new().read(true).open(image_path)?)?;
-        let manifest = WasmImage::load_manifest(&mut archive)?;
-
-        Ok(Self {
-            image_path: image_path.to_owned(),
-            archive,
-            manifest,
-        })
+        let file = OpenOptions::new()
+            .read(true)
+            .open(image_path)
+            .map_err(Error::image_io(format!(
+                "Can't read image file {}.",
+                image_path.display()
+            )))?;
+        Self::from_reader(file)
     }
+}
+
+impl<R: Read + Seek> WasmImage<R> {
+    pub fn from_reader(reader: R) -> Result<Self> {
+        let mut archive =
+            zip::ZipArchive::new(reader).map_err(|e| Error::ManifestInvalid(e.into()))?;
+        let manifest = Self::load_manifest(&mut archive).map_err(Error::ManifestInvalid)?;
 
-    fn load_manifest(archive: &mut ZipArchive<File>) -> Result<Manifest> {
+        Ok(Self { archive, manifest })
+    }
+
+    fn load_manifest(archive: &mut ZipArchive<R>) -> Result<Manifest> {
         let entry = archive.by_name("manifest.json")?;
         Ok(serde_json::from_reader(entry)?)
     }
@@ -90,28 +146,26 @@ impl WasmImage {
             .find(|entry| entry.id == entrypoint_id)
             .cloned();
 
-        Ok(entrypoint.ok_or_else(|| anyhow!("Entrypoint {} not found.", entrypoint_id))?)
+        Ok(entrypoint.ok_or_else(|| Error::EntrypointNotFound(entrypoint_id.to_owned()))?)
     }
 
     pub fn load_binary(&mut self, entrypoint: &EntryPoint) -> Result<Vec<u8>> {
         let image_name = self.manifest.name.clone();
-        let mut entry = self
-            .archive
-            .by_name(&entrypoint.wasm_path)
-            .with_context(|| {
-                format!(
-                    "Can't find file [{}] for entrypoint [{}] in [{}] image.",
-                    entrypoint.wasm_path, entrypoint.id, image_name
-                )
-            })?;
+        let mut entry = self.archive.by_name(&entrypoint.wasm_path).map_err(|e| {
+            Error::ManifestInvalid(anyhow::Error::new(e).context(format!(
+                "Can't find file [{}] for entrypoint [{}] in [{}] image.",
+                entrypoint.wasm_path, entrypoint.id, image_name
+            )))
+        })?;
 
         let mut bytes = vec![];
-        entry.read_to_end(&mut bytes)?;
+        entry
+            .read_to_end(&mut bytes)
+            .map_err(Error::image_io(format!(
+                "Can't load wasm binary {}.",
+                entrypoint.id
+            )))?;
 
         Ok(bytes)
     }
-
-    pub fn path(&self) -> &Path {
-        &self.image_path
-    }
 }
@@ -0,0 +1,502 @@
+use crate::error::Error;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use wasmtime::{Caller, Extern, Linker, Memory, Trap};
+
+/// Module the guest imports network functions from.
+const MODULE: &str = "ya_net";
+const MAX_CONNECTIONS: usize = 64;
+/// Time to resolve a target and connect to any of its addresses, the guest cannot be
+/// interrupted meanwhile.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Blocked `send` and `recv` return [`NetError::Again`] after this time, so that
+/// the guest can be interrupted.
+const IO_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Endpoint in `host:port` form, IPv6 addresses are enclosed in brackets.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Target {
+    host: String,
+    port: u16,
+}
+
+impl Target {
+    /// Resolves host names in a separate thread, which is left behind when `timeout` passes.
+    fn resolve(&self, timeout: Duration) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, self.port)]);
+        }
+        let (tx, rx) = mpsc::channel();
+        let (host, port) = (self.host.clone(), self.port);
+        std::thread::spawn(move || {
+            let addrs = (host.as_str(), port).to_socket_addrs();
+            let _ = tx.send(addrs.map(Iterator::collect));
+        });
+        rx.recv_timeout(timeout).unwrap_or_else(|_| {
+            let msg = format!("{} not resolved in time", self);
+            Err(io::Error::new(io::ErrorKind::TimedOut, msg))
+        })
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let mut last_err = None;
+        for addr in self.resolve(CONNECT_TIMEOUT)? {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout == Duration::from_secs(0) {
+                let err = io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", self));
+                return Err(err);
+            }
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} not resolved", self))
+        }))
+    }
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let sep = s
+            .rfind(':')
+            .ok_or_else(|| anyhow!("Expected host:port instead of [{}].", s))?;
+        let host = &s[..sep];
+        let host = if host.starts_with('[') && host.ends_with(']') {
+            &host[1..host.len() - 1]
+        } else if host.contains(':') {
+            return Err(anyhow!(
+                "IPv6 host has to be enclosed in brackets in [{}].",
+                s
+            ));
+        } else {
+            host
+        };
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '[' || c == ']') {
+            return Err(anyhow!("Invalid host in [{}].", s));
+        }
+        let port = s[sep + 1..]
+            .parse()
+            .map_err(|_| anyhow!("Invalid port in [{}].", s))?;
+        Ok(Target {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Network traffic of the guest, reported after run.
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkUsage {
+    /// Bytes sent by the guest.
+    pub sent: u64,
+    /// Bytes received by the guest.
+    pub received: u64,
+}
+
+/// Status returned to the guest instead of a handle or byte count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NetError {
+    /// Target not declared in the manifest or not allowed by the provider.
+    Denied = -1,
+    BadHandle = -2,
+    Io = -3,
+    /// Nothing was sent or received in time, the call should be retried.
+    Again = -4,
+    /// Too many open connections.
+    Limit = -5,
+    /// Address or buffer is not valid.
+    Invalid = -6,
+}
+
+impl NetError {
+    fn classify(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => NetError::Again,
+            _ => NetError::Io,
+        }
+    }
+
+    fn status(result: Result<i32, NetError>) -> i32 {
+        result.unwrap_or_else(|e| e as i32)
+    }
+}
+
+#[derive(Default)]
+struct NetworkState {
+    targets: Vec<Target>,
+    conns: HashMap<i32, TcpStream>,
+    next_handle: i32,
+    usage: NetworkUsage,
+}
+
+/// Outbound TCP connections of the guest.
+///
+/// The guest may connect only to targets declared in the manifest, all of which have to
+/// be allowed by the provider.
+#[derive(Clone, Default)]
+pub(crate) struct Network(Rc<RefCell<NetworkState>>);
+
+impl Network {
+    /// Fails with [`Error::NetworkRejected`] when a `declared` target is not in `allowlist`.
+    pub fn new(declared: Vec<Target>, allowlist: &[String]) -> Result<Self, Error> {
+        let allowlist = allowlist
+            .iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<Vec<Target>>>()
+            .map_err(|e| Error::Other(e.context("Invalid network allowlist")))?;
+        if let Some(target) = declared.iter().find(|t| !allowlist.contains(t)) {
+            return Err(Error::NetworkRejected(format!(
+                "Network target [{}] is not allowed by the provider.",
+                target
+            )));
+        }
+        Ok(Network(Rc::new(RefCell::new(NetworkState {
+            targets: declared,
+            ..NetworkState::default()
+        }))))
+    }
+
+    pub fn usage(&self) -> NetworkUsage {
+        self.0.borrow().usage
+    }
+
+    fn connect(&self, addr: &str) -> Result<i32, NetError> {
+        let target = addr.parse::<Target>().map_err(|_| NetError::Invalid)?;
+        let mut state = self.0.borrow_mut();
+        if !state.targets.contains(&target) {
+            return Err(NetError::Denied);
+        }
+        if state.conns.len() >= MAX_CONNECTIONS {
+            return Err(NetError::Limit);
+        }
+        let stream = target
+            .connect()
+            .and_then(|stream| {
+                stream.set_read_timeout(Some(IO_TIMEOUT))?;
+                stream.set_write_timeout(Some(IO_TIMEOUT))?;
+                Ok(stream)
+            })
+            .map_err(|e| {
+                log::debug!("Failed to connect to {}: {}", target, e);
+                NetError::Io
+            })?;
+        let handle = state.next_handle;
+        state.next_handle += 1;
+        state.conns.insert(handle, stream);
+        Ok(handle)
+    }
+
+    fn send(&self, handle: i32, data: &[u8]) -> Result<i32, NetError> {
+        let mut state = self.0.borrow_mut();
+        let stream = state.conns.get_mut(&handle).ok_or(NetError::BadHandle)?;
+        let n = stream.write(data).map_err(|e| NetError::classify(&e))?;
+        state.usage.sent += n as u64;
+        Ok(n as i32)
+    }
+
+    fn recv(&self, handle: i32, buf: &mut [u8]) -> Result<i32, NetError> {
+        let mut state = self.0.borrow_mut();
+        let stream = state.conns.get_mut(&handle).ok_or(NetError::BadHandle)?;
+        let n = stream.read(buf).map_err(|e| NetError::classify(&e))?;
+        state.usage.received += n as u64;
+        Ok(n as i32)
+    }
+
+    fn close(&self, handle: i32) -> Result<i32, NetError> {
+        match self.0.borrow_mut().conns.remove(&handle) {
+            Some(_) => Ok(0),
+            None => Err(NetError::BadHandle),
+        }
+    }
+
+    /// Defines `ya_net` functions in `linker`.
+    ///
+    /// * `connect(addr_ptr, addr_len) -> handle`
+    /// * `send(handle, ptr, len) -> bytes sent`
+    /// * `recv(handle, ptr, len) -> bytes received`, `0` when the peer closed the connection
+    /// * `close(handle) -> 0`
+    ///
+    /// Failures are returned as negative status codes.
+    pub fn link(&self, linker: &mut Linker) -> Result<()> {
+        let network = self.clone();
+        linker.func(
+            MODULE,
+            "connect",
+            move |caller: Caller, ptr: i32, len: i32| -> Result<i32, Trap> {
+                let mem = memory(&caller)?;
+                let data = unsafe { mem.data_unchecked() };
+                let addr = &data[guest_range(data.len(), ptr, len)?];
+                Ok(match std::str::from_utf8(addr) {
+                    Ok(addr) => NetError::status(network.connect(addr)),
+                    Err(_) => NetError::Invalid as i32,
+                })
+            },
+        )?;
+
+        let network = self.clone();
+        linker.func(
+            MODULE,
+            "send",
+            move |caller: Caller, handle: i32, ptr: i32, len: i32| -> Result<i32, Trap> {
+                let mem = memory(&caller)?;
+                let data = unsafe { mem.data_unchecked() };
+                let range = guest_range(data.len(), ptr, len)?;
+                Ok(NetError::status(network.send(handle, &data[range])))
+            },
+        )?;
+
+        let network = self.clone();
+        linker.func(
+            MODULE,
+            "recv",
+            move |caller: Caller, handle: i32, ptr: i32, len: i32| -> Result<i32, Trap> {
+                let mem = memory(&caller)?;
+                let data = unsafe { mem.data_unchecked_mut() };
+                let range = guest_range(data.len(), ptr, len)?;
+                Ok(NetError::status(network.recv(handle, &mut data[range])))
+            },
+        )?;
+
+        let network = self.clone();
+        linker.func(MODULE, "close", move |handle: i32| -> i32 {
+            NetError::status(network.close(handle))
+        })?;
+
+        Ok(())
+    }
+}
+
+fn memory(caller: &Caller) -> Result<Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("missing required memory export"))
+}
+
+/// Bounds checked range of guest memory.
+fn guest_range(mem_len: usize, ptr: i32, len: i32) -> Result<Range<usize>, Trap> {
+    let start = ptr as u32 as usize;
+    match start.checked_add(len as u32 as usize) {
+        Some(end) if end <= mem_len => Ok(start..end),
+        _ => Err(Trap::new("out of bounds memory access")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::Package;
+    use crate::{testing, RuntimeOptions};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    /// Guest sending `ping` to `addr` and expecting it back.
+    fn ping_guest(addr: &str) -> String {
+        format!(
+            r#"(module
+                (import "ya_net" "connect" (func $connect (param i32 i32) (result i32)))
+                (import "ya_net" "send" (func $send (param i32 i32 i32) (result i32)))
+                (import "ya_net" "recv" (func $recv (param i32 i32 i32) (result i32)))
+                (import "ya_net" "close" (func $close (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "ping")
+                (data (i32.const 16) "{addr}")
+                (func (export "_start")
+                    (local $conn i32) (local $received i32) (local $n i32)
+                    (local.set $conn (call $connect (i32.const 16) (i32.const {len})))
+                    (if (i32.lt_s (local.get $conn) (i32.const 0)) (then unreachable))
+                    (if (i32.ne (call $send (local.get $conn) (i32.const 0) (i32.const 4))
+                            (i32.const 4))
+                        (then unreachable))
+                    (block $done
+                        (loop $again
+                            (br_if $done (i32.ge_s (local.get $received) (i32.const 4)))
+                            (local.set $n (call $recv (local.get $conn)
+                                (i32.add (i32.const 8) (local.get $received))
+                                (i32.sub (i32.const 4) (local.get $received))))
+                            (br_if $again (i32.eq (local.get $n) (i32.const -4)))
+                            (if (i32.le_s (local.get $n) (i32.const 0)) (then unreachable))
+                            (local.set $received (i32.add (local.get $received) (local.get $n)))
+                            (br $again)))
+                    (if (i32.ne (i32.load (i32.const 8)) (i32.load (i32.const 0)))
+                        (then unreachable))
+                    (drop (call $close (local.get $conn)))))"#,
+            addr = addr,
+            len = addr.len()
+        )
+    }
+
+    #[test]
+    fn test_target() {
+        let target = "Localhost:8080".parse::<Target>().unwrap();
+        assert_eq!(target.to_string(), "localhost:8080");
+        assert_eq!(
+            "[::1]:80".parse::<Target>().unwrap().to_string(),
+            "[::1]:80"
+        );
+        assert!("localhost".parse::<Target>().is_err());
+        assert!("fe80::1:80".parse::<Target>().is_err());
+        assert!("::1:80".parse::<Target>().is_err());
+        assert!(":80".parse::<Target>().is_err());
+        assert!("localhost:http".parse::<Target>().is_err());
+
+        // IP literals are not resolved, so they do not wait for the resolver thread
+        let target = "[::1]:80".parse::<Target>().unwrap();
+        assert_eq!(
+            target.resolve(Duration::from_secs(0)).unwrap(),
+            vec!["[::1]:80".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let echo = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let declared = vec![addr.parse().unwrap()];
+        assert!(matches!(
+            Network::new(declared.clone(), &[]),
+            Err(Error::NetworkRejected(_))
+        ));
+        let network = Network::new(declared, &[addr.clone()]).unwrap();
+        assert_eq!(network.connect("127.0.0.1:1"), Err(NetError::Denied));
+
+        let handle = network.connect(&addr).unwrap();
+        assert_eq!(network.send(handle, b"ping"), Ok(4));
+        let mut buf = [0; 8];
+        let mut received = 0;
+        while received < 4 {
+            match network.recv(handle, &mut buf[received..]) {
+                Ok(n) => received += n as usize,
+                Err(NetError::Again) => continue,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        assert_eq!(&buf[..4], b"ping");
+        assert_eq!(network.close(handle), Ok(0));
+        assert_eq!(network.send(handle, b"x"), Err(NetError::BadHandle));
+        assert_eq!(
+            network.usage(),
+            NetworkUsage {
+                sent: 4,
+                received: 4,
+            }
+        );
+        echo.join().unwrap();
+    }
+
+    #[test]
+    fn test_guest() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let echo = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        });
+
+        let package =
+            testing::package(&ping_guest(&addr), serde_json::json!({ "network": [addr] }));
+        let run = |allowlist: Vec<String>| {
+            RuntimeOptions::default()
+                .with_network_allowlist(allowlist)
+                .run_package(
+                    &mut Package::from_bytes(package.clone()).unwrap(),
+                    Vec::<(String, PathBuf)>::new(),
+                    "main",
+                    Vec::new(),
+                )
+        };
+        assert!(matches!(run(Vec::new()), Err(Error::NetworkRejected(_))));
+        let outcome = run(vec![addr.clone()]).unwrap();
+        assert_eq!(
+            outcome.network,
+            NetworkUsage {
+                sent: 4,
+                received: 4,
+            }
+        );
+        echo.join().unwrap();
+    }
+
+    #[test]
+    fn test_usage_on_trap() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let sink = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+        });
+
+        // sends `ping` and traps
+        let guest = format!(
+            r#"(module
+                (import "ya_net" "connect" (func $connect (param i32 i32) (result i32)))
+                (import "ya_net" "send" (func $send (param i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "ping")
+                (data (i32.const 16) "{addr}")
+                (func (export "_start")
+                    (drop (call $send
+                        (call $connect (i32.const 16) (i32.const {len}))
+                        (i32.const 0) (i32.const 4)))
+                    unreachable))"#,
+            addr = addr,
+            len = addr.len()
+        );
+        let package = testing::package(&guest, serde_json::json!({ "network": [addr] }));
+        let err = RuntimeOptions::default()
+            .with_network_allowlist(vec![addr])
+            .run_package(
+                &mut Package::from_bytes(package).unwrap(),
+                Vec::<(String, PathBuf)>::new(),
+                "main",
+                Vec::new(),
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Trap { .. }));
+        assert_eq!(
+            err.network_usage(),
+            Some(NetworkUsage {
+                sent: 4,
+                received: 0,
+            })
+        );
+        sink.join().unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Receives guest output while the guest is running.
+///
+/// Chunks are delivered as written by the guest, they are not split into lines.
+/// Stdout and stderr are pumped by separate threads.
+pub trait OutputHandler: Send + Sync {
+    /// Handles chunk of the guest stdout.
+    fn handle_stdout(&self, chunk: &[u8]);
+
+    /// Handles chunk of the guest stderr.
+    fn handle_stderr(&self, chunk: &[u8]);
+}
+
+#[derive(Clone)]
+pub(crate) struct SharedOutputHandler(pub Arc<dyn OutputHandler>);
+
+impl fmt::Debug for SharedOutputHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutputHandler")
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Creates a pipe which write end is handed to the guest, and spawns a thread
+/// passing everything read from the pipe to `handler`.
+///
+/// The thread finishes when all copies of the returned file are dropped.
+pub(crate) fn pump(
+    handler: &SharedOutputHandler,
+    stream: Stream,
+) -> io::Result<(File, JoinHandle<()>)> {
+    let (mut reader, writer) = os_pipe::pipe()?;
+    let handler = handler.0.clone();
+    let pump = thread::Builder::new()
+        .name("wasi-output".into())
+        .spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => match stream {
+                        Stream::Stdout => handler.handle_stdout(&buf[..n]),
+                        Stream::Stderr => handler.handle_stderr(&buf[..n]),
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        log::warn!("Failed to read guest output: {}", e);
+                        break;
+                    }
+                }
+            }
+        })?;
+    Ok((into_file(writer), pump))
+}
+
+#[cfg(unix)]
+fn into_file(writer: os_pipe::PipeWriter) -> File {
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    unsafe { File::from_raw_fd(writer.into_raw_fd()) }
+}
+
+#[cfg(windows)]
+fn into_file(writer: os_pipe::PipeWriter) -> File {
+    use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+    unsafe { File::from_raw_handle(writer.into_raw_handle()) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<u8>>, Mutex<Vec<u8>>);
+
+    impl OutputHandler for Collect {
+        fn handle_stdout(&self, chunk: &[u8]) {
+            self.0.lock().unwrap().extend_from_slice(chunk)
+        }
+
+        fn handle_stderr(&self, chunk: &[u8]) {
+            self.1.lock().unwrap().extend_from_slice(chunk)
+        }
+    }
+
+    #[test]
+    fn test_pump() {
+        let collect = Arc::new(Collect::default());
+        let handler = SharedOutputHandler(collect.clone());
+        let (mut out, out_pump) = pump(&handler, Stream::Stdout).unwrap();
+        let (mut err, err_pump) = pump(&handler, Stream::Stderr).unwrap();
+        out.write_all(b"hello ").unwrap();
+        out.write_all(b"world").unwrap();
+        err.write_all(b"oops").unwrap();
+        drop((out, err));
+        out_pump.join().unwrap();
+        err_pump.join().unwrap();
+        assert_eq!(collect.0.lock().unwrap().as_slice(), b"hello world");
+        assert_eq!(collect.1.lock().unwrap().as_slice(), b"oops");
+    }
+}
@@ -0,0 +1,152 @@
+use crate::deploy::absolute_path;
+use crate::entrypoint::{validate_mount_path, DirectoryMount};
+use crate::error::Error;
+use crate::manifest::WasmImage;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek};
+use std::path::PathBuf;
+
+/// Wasm package loaded from memory or any seekable reader.
+///
+/// Unlike [`ya_runtime_wasi::deploy`], nothing is written to a workdir. Manifest mount
+/// points are mapped to host directories given by the caller when running the package
+/// with [`RuntimeOptions::run_package`].
+///
+/// [`ya_runtime_wasi::deploy`]: fn.deploy.html
+/// [`RuntimeOptions::run_package`]: struct.RuntimeOptions.html#method.run_package
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use std::path::PathBuf;
+/// use ya_runtime_wasi::{Package, RuntimeOptions};
+///
+/// let bytes = std::fs::read("package.zip").unwrap();
+/// let mut package = Package::from_bytes(bytes).unwrap();
+///
+/// RuntimeOptions::default()
+///     .run_package(
+///         &mut package,
+///         vec![("/workdir".to_string(), PathBuf::from("/tmp/workdir"))],
+///         "hello",
+///         vec!["/workdir/input".into(), "/workdir/output".into()],
+///     )
+///     .unwrap();
+/// ```
+pub struct Package<R = Cursor<Vec<u8>>> {
+    pub(crate) image: WasmImage<R>,
+}
+
+impl Package {
+    /// Loads package from zip archive bytes.
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Result<Self, Error> {
+        Self::from_reader(Cursor::new(bytes.into()))
+    }
+}
+
+impl<R: Read + Seek> Package<R> {
+    /// Loads package from a reader of zip archive.
+    pub fn from_reader(reader: R) -> Result<Self, Error> {
+        Ok(Package {
+            image: WasmImage::from_reader(reader)?,
+        })
+    }
+
+    /// Returns ids of package entrypoints.
+    pub fn entrypoints(&self) -> impl Iterator<Item = &str> {
+        self.image
+            .manifest
+            .entry_points
+            .iter()
+            .map(|entry| entry.id.as_str())
+    }
+
+    /// Returns guest paths of mount points declared by the package.
+    pub fn mount_points(&self) -> impl Iterator<Item = String> + '_ {
+        self.image
+            .manifest
+            .mount_points
+            .iter()
+            .map(|mount_point| absolute_path(mount_point.path()).into_owned())
+    }
+
+    /// Maps every mount point to one of the host directories, keyed by guest path.
+    pub(crate) fn mounts(
+        &self,
+        host_dirs: impl IntoIterator<Item = (String, PathBuf)>,
+    ) -> Result<Vec<DirectoryMount>, Error> {
+        let quotas = self.image.manifest.quotas()?;
+        let mut host_dirs = host_dirs
+            .into_iter()
+            .map(|(guest, host)| (absolute_path(&guest).into_owned(), host))
+            .collect::<HashMap<_, _>>();
+        let mounts = self
+            .mount_points()
+            .map(|guest| {
+                let host = match host_dirs.remove(&guest) {
+                    Some(host) => host,
+                    None => {
+                        return Err(Error::MountRejected(format!(
+                            "No host directory for mount point [{}].",
+                            guest
+                        )))
+                    }
+                };
+                let quota = quotas.get(&guest).copied();
+                let guest = PathBuf::from(guest);
+                validate_mount_path(&guest)?;
+                Ok(DirectoryMount { host, guest, quota })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if let Some(guest) = host_dirs.keys().next() {
+            return Err(Error::MountRejected(format!(
+                "Package has no mount point [{}].",
+                guest
+            )));
+        }
+        Ok(mounts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn package() -> Package {
+        let manifest = serde_json::json!({
+            "mount-points": [{"rw": "workdir"}, {"private": "/tmp"}],
+            "quotas": {"/workdir": "1m"},
+        });
+        Package::from_bytes(testing::package("(module)", manifest)).unwrap()
+    }
+
+    #[test]
+    fn test_mounts() {
+        let package = package();
+        assert_eq!(package.entrypoints().collect::<Vec<_>>(), vec!["main"]);
+        assert_eq!(
+            package.mount_points().collect::<Vec<_>>(),
+            vec!["/workdir", "/tmp"]
+        );
+
+        let host = |guest: &str, host: &str| (guest.to_string(), PathBuf::from(host));
+        let mounts = package
+            .mounts(vec![host("workdir", "/data/w"), host("/tmp", "/data/t")])
+            .unwrap();
+        assert_eq!(mounts[0].guest, PathBuf::from("/workdir"));
+        assert_eq!(mounts[0].host, PathBuf::from("/data/w"));
+        assert_eq!(mounts[0].quota, Some(0x100_000));
+        assert_eq!(mounts[1].quota, None);
+
+        assert!(package.mounts(vec![host("/workdir", "/data/w")]).is_err());
+        assert!(package
+            .mounts(vec![
+                host("/workdir", "/data/w"),
+                host("/tmp", "/data/t"),
+                host("/etc", "/etc"),
+            ])
+            .is_err());
+    }
+}
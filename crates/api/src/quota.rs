@@ -0,0 +1,132 @@
+use crate::entrypoint::DirectoryMount;
+
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use ya_runtime_common::dir_size;
+
+/// Disk usage of a mounted directory, reported after run.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeUsage {
+    /// Guest path of the volume.
+    pub path: String,
+    /// Size of files in the volume, in bytes.
+    pub used: u64,
+    /// Volume quota, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+}
+
+#[derive(Clone)]
+struct Quota {
+    guest: String,
+    host: PathBuf,
+    limit: Option<u64>,
+}
+
+/// Quotas of mounted directories.
+///
+/// Preopened directories are accessed by the guest directly, so writes are not limited,
+/// usage is checked by walking the directories instead.
+#[derive(Clone)]
+pub(crate) struct Quotas {
+    vols: Vec<Quota>,
+}
+
+impl Quotas {
+    /// Takes quotas of `mounts`, capped by `max_volume_size`.
+    pub fn new(mounts: &[DirectoryMount], max_volume_size: Option<u64>) -> Self {
+        let vols = mounts
+            .iter()
+            .map(|mount| Quota {
+                guest: mount.guest.display().to_string(),
+                host: mount.host.clone(),
+                limit: match (mount.quota, max_volume_size) {
+                    (Some(quota), Some(max)) => Some(quota.min(max)),
+                    (quota, max) => quota.or(max),
+                },
+            })
+            .collect();
+        Quotas { vols }
+    }
+
+    /// Tells whether any volume has a quota.
+    pub fn is_limited(&self) -> bool {
+        self.vols.iter().any(|vol| vol.limit.is_some())
+    }
+
+    /// Returns guest path of the first volume over its quota.
+    pub fn exceeded(&self) -> io::Result<Option<&str>> {
+        for vol in &self.vols {
+            if let Some(limit) = vol.limit {
+                if dir_size(&vol.host)? > limit {
+                    return Ok(Some(&vol.guest));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns disk usage of all volumes.
+    pub fn usage(&self) -> io::Result<Vec<VolumeUsage>> {
+        self.vols
+            .iter()
+            .map(|vol| {
+                Ok(VolumeUsage {
+                    path: vol.guest.clone(),
+                    used: dir_size(&vol.host)?,
+                    quota: vol.limit,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quotas() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = |name: &str, quota: Option<u64>| {
+            let host = dir.path().join(name);
+            std::fs::create_dir_all(host.join("sub")).unwrap();
+            DirectoryMount {
+                host,
+                guest: PathBuf::from(format!("/{}", name)),
+                quota,
+            }
+        };
+        let mounts = vec![mount("a", Some(8)), mount("b", None)];
+        std::fs::write(dir.path().join("a/sub/x"), b"abcd").unwrap();
+        std::fs::write(dir.path().join("b/x"), b"abcdefgh").unwrap();
+
+        let quotas = Quotas::new(&mounts, None);
+        assert!(quotas.is_limited());
+        assert_eq!(quotas.exceeded().unwrap(), None);
+        assert!(!Quotas::new(&mounts[1..], None).is_limited());
+
+        std::fs::create_dir_all(dir.path().join("a/sub/sub/sub")).unwrap();
+        std::fs::write(dir.path().join("a/sub/sub/sub/y"), b"ef").unwrap();
+
+        let quotas = Quotas::new(&mounts, Some(6));
+        assert_eq!(quotas.exceeded().unwrap(), Some("/b"));
+        assert_eq!(
+            quotas.usage().unwrap(),
+            vec![
+                VolumeUsage {
+                    path: "/a".into(),
+                    used: 6,
+                    quota: Some(6),
+                },
+                VolumeUsage {
+                    path: "/b".into(),
+                    used: 8,
+                    quota: Some(6),
+                },
+            ]
+        );
+    }
+}
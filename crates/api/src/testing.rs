@@ -0,0 +1,32 @@
+//! Packages with guests written in WebAssembly text format, for tests.
+
+use serde_json::json;
+use std::io::{Cursor, Write};
+use zip::write::{FileOptions, ZipWriter};
+
+/// Guest spinning until interrupted.
+pub(crate) const SPIN: &str = r#"(module
+    (memory (export "memory") 1)
+    (func (export "_start") (loop br 0)))"#;
+
+/// Builds package with a single `main` entrypoint compiled from `wat`.
+///
+/// Fields of `manifest` are added to the generated manifest.
+pub(crate) fn package(wat: &str, manifest: serde_json::Value) -> Vec<u8> {
+    let mut full = json!({
+        "id": "test",
+        "name": "test",
+        "entry-points": [{"id": "main", "wasm-path": "main.wasm"}],
+    });
+    if let (Some(full), Some(fields)) = (full.as_object_mut(), manifest.as_object()) {
+        full.extend(fields.clone());
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("manifest.json", FileOptions::default())
+        .unwrap();
+    zip.write_all(full.to_string().as_bytes()).unwrap();
+    zip.start_file("main.wasm", FileOptions::default()).unwrap();
+    zip.write_all(&wat::parse_str(wat).unwrap()).unwrap();
+    zip.finish().unwrap().into_inner()
+}
@@ -1,27 +1,66 @@
 use crate::{
-    entrypoint::DirectoryMount,
+    entrypoint::{DirectoryMount, RunOutcome},
     manifest::{EntryPoint, WasmImage},
+    network::Network,
 };
 
-use wasi_common::{self, preopen_dir, WasiCtxBuilder};
-use wasmtime::{Engine, Linker, Module, OptLevel, Store, Trap};
+use wasi_common::{self, preopen_dir, OsOther, WasiCtxBuilder};
+use wasmtime::{Engine, Linker, Module, OptLevel, Store};
 use wasmtime_wasi::Wasi;
 
 use crate::entrypoint::RuntimeOptions;
+use crate::error::Error;
+use crate::handle::Cancel;
+use crate::output::{self, SharedOutputHandler, Stream};
+use crate::quota::Quotas;
 use anyhow::{anyhow, bail, Context, Result};
 use log::info;
-use std::{collections::HashMap, fs::File};
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::{collections::HashMap, convert::TryFrom, fs::File, io};
+
+/// How often volume sizes are checked against quotas while the guest runs.
+const QUOTA_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 pub(crate) struct Wasmtime {
     linker: Linker,
     mounts: Vec<DirectoryMount>,
     /// Modules loaded by the user.
     modules: HashMap<EntryPoint, Module>,
+    timeout: Option<Duration>,
+    cancel: Option<Cancel>,
+    quotas: Quotas,
+    network: Network,
+    output_handler: Option<SharedOutputHandler>,
+    /// Threads delivering guest output to the handler.
+    pumps: Vec<JoinHandle<()>>,
+}
+
+/// Writes wasmtime cache config, storing compiled modules in `cache_dir`.
+fn cache_config(cache_dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(cache_dir)?;
+    let config_path = cache_dir.join("wasmtime-cache.toml");
+    std::fs::write(
+        &config_path,
+        format!(
+            "[cache]\nenabled = true\ndirectory = {:?}\n",
+            cache_dir.display().to_string()
+        ),
+    )?;
+    Ok(config_path)
 }
 
 impl Wasmtime {
-    pub fn new(mounts: Vec<DirectoryMount>, options: RuntimeOptions) -> Self {
-        let store = if options.is_default() {
+    pub fn new(
+        mounts: Vec<DirectoryMount>,
+        network: Network,
+        options: RuntimeOptions,
+    ) -> Result<Self> {
+        let quotas = Quotas::new(&mounts, options.max_volume_size);
+        let store = if options.is_default() && !quotas.is_limited() {
             Store::default()
         } else {
             let mut config = wasmtime::Config::new();
@@ -40,22 +79,39 @@ impl Wasmtime {
                 config.debug_info(false);
                 config.interruptable(false);
             }
+            if options.timeout.is_some() || options.cancel.is_some() || quotas.is_limited() {
+                config.interruptable(true);
+            }
+            if let Some(cache_dir) = &options.cache_dir {
+                config
+                    .cache_config_load(cache_config(cache_dir)?)
+                    .context("Failed to configure module cache")?;
+            }
 
             let engine = Engine::new(&config);
             Store::new(&engine)
         };
 
+        if let Some(cancel) = &options.cancel {
+            cancel.register(store.interrupt_handle()?);
+        }
         let linker = Linker::new(&store);
         let modules = HashMap::new();
 
-        Self {
+        Ok(Self {
             linker,
             mounts,
             modules,
-        }
+            timeout: options.timeout,
+            cancel: options.cancel,
+            quotas,
+            network,
+            output_handler: options.output_handler,
+            pumps: Vec::new(),
+        })
     }
 
-    pub fn load_binaries(&mut self, mut image: &mut WasmImage) -> Result<()> {
+    pub fn load_binaries<R: Read + Seek>(&mut self, mut image: &mut WasmImage<R>) -> Result<()> {
         // Loading binary will validate if it can be correctly loaded by wasmtime.
         for entrypoint in &image.list_entrypoints() {
             self.load_binary(&mut image, entrypoint)?;
@@ -68,6 +124,7 @@ impl Wasmtime {
         let args = Wasmtime::compute_args(&args, &image);
         let preopens = self.compute_preopens()?;
         self.add_wasi_modules(&args, &preopens)?;
+        self.network.link(&mut self.linker)?;
 
         info!("Running wasm binary with arguments {:?}", args);
         self.invoke(&image)?;
@@ -75,19 +132,39 @@ impl Wasmtime {
         Ok(())
     }
 
-    pub fn load_binary(&mut self, image: &mut WasmImage, entrypoint: &EntryPoint) -> Result<()> {
+    /// Returns disk usage of mounted volumes and network traffic of the guest.
+    pub fn outcome(&self) -> Result<RunOutcome> {
+        Ok(RunOutcome {
+            volumes: self.quotas.usage()?,
+            network: self.network.usage(),
+        })
+    }
+
+    /// Releases the guest and waits until its output is delivered.
+    pub fn finish(self) {
+        let Wasmtime { linker, pumps, .. } = self;
+        drop(linker);
+        for pump in pumps {
+            let _ = pump.join();
+        }
+    }
+
+    pub fn load_binary<R: Read + Seek>(
+        &mut self,
+        image: &mut WasmImage<R>,
+        entrypoint: &EntryPoint,
+    ) -> Result<()> {
         info!("Loading wasm binary: {}.", entrypoint.id);
 
         let wasm_binary = image
             .load_binary(entrypoint)
             .with_context(|| format!("Can't load wasm binary {}.", entrypoint.id))?;
 
-        let module = Module::new(self.linker.store().engine(), wasm_binary).with_context(|| {
-            format!(
-                "Failed to create Wasm module for binary: '{}'",
-                entrypoint.id
-            )
-        })?;
+        let module =
+            Module::new(self.linker.store().engine(), wasm_binary).map_err(|e| Error::Compile {
+                module: entrypoint.id.clone(),
+                source: e,
+            })?;
 
         if self.modules.insert(entrypoint.to_owned(), module).is_some() {
             bail!("Module already defined: '{}'", entrypoint.id);
@@ -101,63 +178,176 @@ impl Wasmtime {
             Some(module) => module,
             None => bail!("Module not found: '{}'", entrypoint.id),
         };
-        self.linker
-            .module(&entrypoint.id, &module)
-            .with_context(|| format!("Failed to instantiate module: '{}'", entrypoint.id))?;
+        let link_error = |source| Error::Link {
+            module: entrypoint.id.clone(),
+            source,
+        };
+        // Instantiated directly, as `Linker::module` keeps the imports of a command
+        // in the store, which is then never released together with the guest stdio.
+        let instance = self.linker.instantiate(module).map_err(link_error)?;
 
         // TODO for now, we only allow invoking default Wasm export per module,
         // i.e., `_start` export. In the future, it could be useful to allow
         // invoking custom exports as well.
-        let run = self
-            .linker
-            .get_default(&entrypoint.id)?
-            .get0::<()>()
+        let run = instance
+            .get_func("")
+            .or_else(|| instance.get_func("_start"))
+            .ok_or_else(|| anyhow!("missing default export"))
+            .and_then(|func| func.get0::<()>())
             .context(
                 "Failed to find '_start' export in module; did you build a library by mistake?",
-            )?;
-
-        if let Err(err) =
-            run().with_context(|| format!("Failed to run module: '{}'", entrypoint.id))
-        {
-            match err.downcast_ref::<Trap>() {
-                None => return Err(err),
-                Some(trap) => {
-                    // TODO bubble up exit code.
-                    let exit_code = match trap.i32_exit_status() {
-                        Some(status) => {
-                            // On Windows, exit status 3 indicates an abort (see below),
-                            // so return 1 indicating a non-zero status to avoid ambiguity.
-                            if cfg!(windows) && status >= 3 {
-                                1
-                            } else {
-                                status
-                            }
-                        }
-                        None => {
-                            if cfg!(windows) {
-                                // On Windows, return 3.
-                                // https://docs.microsoft.com/en-us/cpp/c-runtime-library/reference/abort?view=vs-2019
-                                3
-                            } else {
-                                128 + libc::SIGABRT
-                            }
-                        }
-                    };
-
-                    return Err(anyhow!("{}; exit_code={}", err, exit_code));
+            )
+            .map_err(link_error)?;
+
+        let timer = match self.timeout {
+            Some(timeout) => Some((timeout, self.start_timer(timeout)?)),
+            None => None,
+        };
+        let watcher = if self.quotas.is_limited() {
+            Some(self.start_quota_watcher()?)
+        } else {
+            None
+        };
+        let result = run();
+        if let Some((done, watcher)) = watcher {
+            drop(done);
+            let _ = watcher.join();
+        }
+        let network = self.network.usage();
+        if let Some((timeout, (done, timed_out))) = timer {
+            drop(done);
+            if timed_out.recv().unwrap_or(false) && result.is_err() {
+                let err = io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Execution timed out after {:?}", timeout),
+                );
+                return Err(Error::LimitExceeded {
+                    network,
+                    source: err,
+                }
+                .into());
+            }
+        }
+
+        // Volumes which usage cannot be checked are treated as over quota.
+        let exceeded = match self.quotas.exceeded() {
+            Ok(path) => path.map(|path| format!("Volume quota exceeded: {}", path)),
+            Err(e) => Some(format!("Failed to check volume quotas: {}", e)),
+        };
+        if let Some(message) = exceeded {
+            let err = io::Error::new(io::ErrorKind::Other, message);
+            return Err(Error::LimitExceeded {
+                network,
+                source: err,
+            }
+            .into());
+        }
+
+        if result.is_err() && self.cancel.as_ref().map_or(false, Cancel::is_cancelled) {
+            return Err(Error::Cancelled { network }.into());
+        }
+
+        if let Err(trap) = result {
+            let exit_code = match trap.i32_exit_status() {
+                Some(status) => {
+                    // On Windows, exit status 3 indicates an abort (see below),
+                    // so return 1 indicating a non-zero status to avoid ambiguity.
+                    if cfg!(windows) && status >= 3 {
+                        1
+                    } else {
+                        status
+                    }
+                }
+                None => {
+                    if cfg!(windows) {
+                        // On Windows, return 3.
+                        // https://docs.microsoft.com/en-us/cpp/c-runtime-library/reference/abort?view=vs-2019
+                        3
+                    } else {
+                        128 + libc::SIGABRT
+                    }
                 }
+            };
+
+            return Err(Error::Trap {
+                module: entrypoint.id.clone(),
+                exit_code,
+                network,
+                source: trap,
             }
+            .into());
         }
 
         Ok(())
     }
 
+    /// Interrupts the guest when `timeout` passes before the returned sender is dropped.
+    ///
+    /// The receiver tells whether the guest was interrupted.
+    fn start_timer(&self, timeout: Duration) -> Result<(mpsc::Sender<()>, mpsc::Receiver<bool>)> {
+        let interrupt_handle = self.linker.store().interrupt_handle()?;
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let (timed_out_tx, timed_out_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let timed_out = match done_rx.recv_timeout(timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    interrupt_handle.interrupt();
+                    true
+                }
+                _ => false,
+            };
+            let _ = timed_out_tx.send(timed_out);
+        });
+        Ok((done_tx, timed_out_rx))
+    }
+
+    /// Interrupts the guest when a volume exceeds its quota or its usage cannot be checked,
+    /// until the returned sender is dropped.
+    fn start_quota_watcher(&self) -> Result<(mpsc::Sender<()>, JoinHandle<()>)> {
+        let interrupt_handle = self.linker.store().interrupt_handle()?;
+        let quotas = self.quotas.clone();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let watcher = std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) =
+                done_rx.recv_timeout(QUOTA_POLL_INTERVAL)
+            {
+                match quotas.exceeded() {
+                    Ok(None) => (),
+                    Ok(Some(_)) => {
+                        interrupt_handle.interrupt();
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to check volume quotas: {}", e);
+                        interrupt_handle.interrupt();
+                        break;
+                    }
+                }
+            }
+        });
+        Ok((done_tx, watcher))
+    }
+
     fn add_wasi_modules(&mut self, args: &[String], preopens: &[(String, File)]) -> Result<()> {
         info!("Loading wasi.");
 
+        let stdio = match &self.output_handler {
+            Some(handler) => {
+                let (stdout, stdout_pump) = output::pump(handler, Stream::Stdout)?;
+                let (stderr, stderr_pump) = output::pump(handler, Stream::Stderr)?;
+                self.pumps.extend(vec![stdout_pump, stderr_pump]);
+                Some((stdout, stderr))
+            }
+            None => None,
+        };
+
         // Add snapshot1 of WASI ABI
         let mut cx = WasiCtxBuilder::new();
         cx.inherit_stdio().args(args);
+        if let Some((stdout, stderr)) = &stdio {
+            cx.stdout(OsOther::try_from(stdout.try_clone()?)?)
+                .stderr(OsOther::try_from(stderr.try_clone()?)?);
+        }
 
         for (name, file) in preopens {
             cx.preopened_dir(file.try_clone()?, name);
@@ -170,6 +360,9 @@ impl Wasmtime {
         // Add snapshot0 of WASI ABI
         let mut cx = wasi_common::old::snapshot_0::WasiCtxBuilder::new();
         cx.inherit_stdio().args(args);
+        if let Some((stdout, stderr)) = stdio {
+            cx.stdout(stdout).stderr(stderr);
+        }
 
         for (name, file) in preopens {
             cx.preopened_dir(file.try_clone()?, name);
@@ -185,7 +378,7 @@ impl Wasmtime {
     fn compute_preopens(&self) -> Result<Vec<(String, File)>> {
         let mut preopen_dirs = Vec::new();
 
-        for DirectoryMount { guest, host } in &self.mounts {
+        for DirectoryMount { guest, host, .. } in &self.mounts {
             info!("Mounting: {}", guest.display());
 
             preopen_dirs.push((
@@ -193,8 +386,9 @@ impl Wasmtime {
                     .to_str()
                     .ok_or_else(|| anyhow!("Invalid UTF8: guest = '{}'", guest.display()))?
                     .to_owned(),
-                preopen_dir(host)
-                    .with_context(|| format!("Failed to mount '{}'", guest.display()))?,
+                preopen_dir(host).map_err(|e| {
+                    Error::MountRejected(format!("Failed to mount '{}': {}", guest.display(), e))
+                })?,
             ));
         }
 
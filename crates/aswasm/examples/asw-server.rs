@@ -1,15 +1,13 @@
 use secp256k1::PublicKey;
 
-use actix_web::{web, App, HttpServer};
-use actix_web::{HttpResponse, Responder};
-use futures::prelude::*;
-use std::convert::TryFrom;
 use std::fs;
 use std::path::PathBuf;
 use structopt::StructOpt;
 use wasmtime::{Caller, ExportType, ImportType, Linker, Module, Store, Trap};
-use ya_runtime_aswasm::runtime::{link_eth, link_io, Allocator, AsMem};
-use ya_runtime_aswasm::service::{ApplicationChannel, Command};
+use ya_runtime_aswasm::runtime::{
+    link_aead, link_context, link_ed25519, link_eth, link_io, link_keystore, link_logging,
+    link_p256, ActivityInfo, Allocator, AsMem, ContextFactory, ContextHandle, LogConfig,
+};
 
 #[derive(StructOpt)]
 enum Commands {
@@ -49,43 +47,49 @@ impl RunCommand {
                   line: u32,
                   _column: u32|
                   -> Result<(), Trap> {
-                let mem = AsMem::try_from(caller)?;
+                let mem = AsMem::for_caller(&caller, None)?;
                 let message = mem.decode_str(message_ptr)?;
                 eprintln!("at [{}:{}] {}", mem.decode_str(file_name)?, line, message);
                 Err(Trap::new(message))
             },
         )?;
-        //linker.define("ya", "context", Extern)
-        linker.func(
-            "ya",
-            "context",
-            move |caller: Caller| -> Result<i32, Trap> {
-                let mut a = Allocator::for_caller(&caller)?;
-                let string_ptr = a.new_string("reqc1")?;
-                let s = a.retain(string_ptr)?.to_le_bytes();
-                let ptr = a.new_bytes(&[0, 0, 0, 0, 0, 0, 0, 0, s[0], s[1], s[2], s[3]])?;
-                Ok(ptr)
-            },
-        )?;
+        let context = ContextHandle::default();
+        link_context("ya", &mut linker, None, context.clone())?;
         linker.func(
             "ya",
             "log",
             move |caller: Caller, message: i32| -> Result<(), Trap> {
-                let mem = AsMem::try_from(caller)?;
+                let mem = AsMem::for_caller(&caller, None)?;
                 eprintln!("log: {}", mem.decode_str(message)?);
                 Ok::<_, Trap>(())
             },
         )?;
-        link_eth("ya", &mut linker)?;
-        link_io("ya", &mut linker, PathBuf::from("/tmp/w"), Vec::new())?;
+        link_eth("ya", &mut linker, None)?;
+        link_ed25519("ya", &mut linker, None)?;
+        link_p256("ya", &mut linker, None)?;
+        link_aead("ya", &mut linker, None)?;
+        link_keystore("ya", &mut linker, None, None)?;
+        let mut log_config = LogConfig::new("asw-server");
+        log_config.mirror_stderr = true;
+        link_logging("ya", &mut linker, None, log_config, |message| {
+            eprint!("{}", String::from_utf8_lossy(message))
+        })?;
+        link_io(
+            "ya",
+            &mut linker,
+            None,
+            PathBuf::from("/tmp/w"),
+            Vec::new(),
+            Default::default(),
+        )?;
 
         linker.func(
             "ya",
             "eth.toPubKey",
             |caller: Caller, ptr: i32| -> Result<i32, Trap> {
-                let mem = AsMem::for_caller(&caller)?;
+                let mem = AsMem::for_caller(&caller, None)?;
                 let secret = mem.decode_secret(ptr)?;
-                let mut a = Allocator::for_caller(&caller)?;
+                let mut a = Allocator::for_caller(&caller, None)?;
                 let out_ptr =
                     a.new_bytes(PublicKey::from_secret_key(&secret).serialize().as_ref())?;
                 a.retain(out_ptr)
@@ -94,6 +98,8 @@ impl RunCommand {
 
         let app = linker.instantiate(&module).unwrap();
         let f = app.get_func(self.command.as_str()).unwrap();
+        let _context = context
+            .enter(ContextFactory::new(ActivityInfo::default(), None).next(0, &self.command));
         let _rv = f.call(&[])?;
         Ok(())
     }
@@ -103,37 +109,11 @@ impl RunCommand {
 struct ServerCommand {
     #[structopt(long, short)]
     workdir: Option<PathBuf>,
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    bind: String,
     package: PathBuf,
 }
 
-#[actix_web::post("/run/{entryPoint}")]
-async fn do_run(
-    p: web::Path<(String,)>,
-    app: web::Data<ApplicationChannel>,
-    body: web::Json<Vec<String>>,
-) -> impl Responder {
-    let entry_point = p.into_inner().0;
-    let (rx, mut tx) = futures::channel::mpsc::unbounded();
-    let _ = app.send(Command {
-        pid: 0,
-        entry_point,
-        args: body.0,
-        status: rx,
-    });
-    let mut stdout = Vec::<u8>::new();
-    let mut stderr = Vec::<u8>::new();
-    while let Some(e) = tx.next().await {
-        stdout.extend(&e.stdout);
-        stderr.extend(&e.stderr);
-    }
-    let stdout = String::from_utf8_lossy(&stdout);
-    let stderr = String::from_utf8_lossy(&stderr);
-    HttpResponse::Ok().json(serde_json::json!({
-           "stdout": stdout,
-           "stderr": stderr
-    }))
-}
-
 impl ServerCommand {
     async fn exec(&self) -> anyhow::Result<()> {
         let temp_dir = tempfile::tempdir()?;
@@ -144,16 +124,13 @@ impl ServerCommand {
             .unwrap_or_else(|| temp_dir.as_ref());
         let deploy = ya_runtime_aswasm::deploy(work_dir, &self.package)?;
         eprintln!("{:?}", deploy);
-        let app = ya_runtime_aswasm::service::spawn_application(work_dir.to_owned());
-        HttpServer::new(move || {
-            let app = app.clone();
-            App::new().data(app).service(do_run)
-        })
-        .bind("127.0.0.1:8080")?
-        .run()
-        .await?;
-
-        Ok(())
+        ya_runtime_aswasm::server::serve(
+            work_dir.to_owned(),
+            ActivityInfo::default(),
+            Default::default(),
+            &self.bind,
+        )
+        .await
     }
 }
 
@@ -1,15 +1,19 @@
 use crate::image::Image;
-use crate::runtime::Allocator;
+use crate::runtime::{AbiVersion, AccessMode, Allocator, Volume};
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::ops::Not;
 use std::path::{Path, PathBuf};
 use wasmtime::Instance;
 use ya_runtime_api::deploy::{ContainerVolume, DeployResult, StartMode};
+use ya_runtime_common::Size;
 
 pub const MANIFEST_FILE: &str = "manifest.json";
+const DEPLOY_FILE: &str = "deploy.json";
+/// File in the work dir keeping invocation counter.
+pub(crate) const INVOCATION_COUNTER_FILE: &str = "invocations";
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -27,6 +31,11 @@ pub(crate) struct Manifest {
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub mount_points: Vec<MountPoint>,
+
+    /// Disk quotas keyed by mount point path.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub quotas: HashMap<String, Size>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -45,6 +54,15 @@ impl MountPoint {
         }
     }
 
+    pub fn mode(&self) -> AccessMode {
+        match self {
+            Self::Ro(_) => AccessMode::Ro,
+            Self::Rw(_) => AccessMode::Rw,
+            Self::Wo(_) => AccessMode::Wo,
+            Self::Private(_) => AccessMode::Private,
+        }
+    }
+
     pub fn is_public(&self) -> bool {
         matches!(self, Self::Private(_)).not()
     }
@@ -54,6 +72,10 @@ impl MountPoint {
 #[serde(rename_all = "kebab-case")]
 pub struct MainEntry {
     pub wasm_path: String,
+    /// AssemblyScript runtime ABI, detected from module exports when not set.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abi: Option<AbiVersion>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -69,14 +91,31 @@ impl EntryPoint {
     pub fn convert_args(
         &self,
         instance: &Instance,
+        abi: Option<AbiVersion>,
         args: Vec<String>,
     ) -> anyhow::Result<Vec<wasmtime::Val>> {
         args.into_iter()
             .zip(self.args.iter())
-            .map(|(str_val, arg_desc)| arg_desc.convert_arg(instance, str_val))
+            .map(|(str_val, arg_desc)| arg_desc.convert_arg(instance, abi, str_val))
             .collect::<anyhow::Result<Vec<_>>>()
             .with_context(|| format!("converting args {:?}", self.args))
     }
+
+    /// Validates arguments without touching guest memory.
+    pub fn check_args(&self, args: &[String]) -> anyhow::Result<()> {
+        if args.len() != self.args.len() {
+            anyhow::bail!("expected {} args, got {}", self.args.len(), args.len());
+        }
+        for (idx, (arg, arg_desc)) in args.iter().zip(self.args.iter()).enumerate() {
+            arg_desc.check_arg(arg).with_context(|| {
+                format!(
+                    "invalid arg {}",
+                    arg_desc.name().unwrap_or(&idx.to_string())
+                )
+            })?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -88,8 +127,35 @@ pub struct ArgDesc {
 }
 
 impl ArgDesc {
-    fn convert_arg(&self, instance: &Instance, arg: String) -> anyhow::Result<wasmtime::Val> {
-        let mut a = Allocator::for_instance(instance)?;
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn check_arg(&self, arg: &str) -> anyhow::Result<()> {
+        match &self.arg_type {
+            ArgType::String {} => (),
+            ArgType::Bytes { fixed } => {
+                let data = hex::decode(arg)?;
+                if let Some(len) = fixed {
+                    if data.len() != *len {
+                        anyhow::bail!("expected {} bytes, got {}", len, data.len());
+                    }
+                }
+            }
+            ArgType::I32 => {
+                let _: i32 = arg.parse()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn convert_arg(
+        &self,
+        instance: &Instance,
+        abi: Option<AbiVersion>,
+        arg: String,
+    ) -> anyhow::Result<wasmtime::Val> {
+        let mut a = Allocator::for_instance(instance, abi)?;
         match &self.arg_type {
             ArgType::String {} => {
                 // TODO: retain/release
@@ -136,34 +202,58 @@ impl Default for Output {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Deployment {
+    /// Application name from the manifest.
+    #[serde(default)]
+    name: String,
     entry_points: HashMap<String, EntryPoint>,
     image_path: PathBuf,
     main: MainEntry,
-    vols: Vec<ContainerVolume>,
+    vols: Vec<Volume>,
+    /// Name of the private volume holding the keystore.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keystore: Option<String>,
 }
 
 impl Deployment {
     pub fn save(&self, work_dir: &Path) -> anyhow::Result<()> {
-        let deploy_file = work_dir.join("deploy.json");
-        serde_json::to_writer_pretty(
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(deploy_file)?,
-            self,
-        )?;
+        let deploy_file = work_dir.join(DEPLOY_FILE);
+        serde_json::to_writer_pretty(File::create(deploy_file)?, self)?;
         Ok(())
     }
 
     pub fn load(work_dir: &Path) -> anyhow::Result<Self> {
-        let deploy_file = work_dir.join("deploy.json");
+        let deploy_file = work_dir.join(DEPLOY_FILE);
         Ok(serde_json::from_slice(&std::fs::read(deploy_file)?)?)
     }
 
+    /// Loads deployment when the work dir was deployed to.
+    fn load_existing(work_dir: &Path) -> anyhow::Result<Option<Self>> {
+        if work_dir.join(DEPLOY_FILE).exists() {
+            Ok(Some(Self::load(work_dir)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Removes volumes not used by `next` deployment.
+    fn remove_vols_unused_by(&self, work_dir: &Path, next: &Deployment) -> anyhow::Result<()> {
+        for vol in &self.vols {
+            if !next.vols.iter().any(|v| v.name == vol.name) {
+                remove_dir(&work_dir.join(&vol.name))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_image(&self) -> anyhow::Result<Image<File>> {
         Ok(Image::from_path(&self.image_path)?)
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     #[inline]
     pub fn main_entry(&self) -> &MainEntry {
         &self.main
@@ -173,52 +263,135 @@ impl Deployment {
         self.entry_points.get(name)
     }
 
-    pub fn vols(&self) -> Vec<ContainerVolume> {
-        self.vols
-            .iter()
-            .map(|v| ContainerVolume {
-                name: v.name.clone(),
-                path: v.path.clone(),
-            })
-            .collect()
+    pub fn entry_points(&self) -> &HashMap<String, EntryPoint> {
+        &self.entry_points
+    }
+
+    pub fn vols(&self) -> Vec<Volume> {
+        self.vols.clone()
+    }
+
+    pub fn keystore_dir(&self, work_dir: &Path) -> Option<PathBuf> {
+        self.keystore.as_ref().map(|name| work_dir.join(name))
     }
 }
 
-pub fn deploy(workdir: &Path, path: &Path) -> anyhow::Result<DeployResult> {
-    let mut image = Image::from_path(path)?;
-    let manifest: Manifest = image.get_json(MANIFEST_FILE)?;
-
-    let mut vols = Vec::new();
-    let mut public_vols = Vec::new();
-    for mount_point in manifest.mount_points {
-        let name = format!("vol-{}", uuid::Uuid::new_v4());
-        let dir = workdir.join(&name);
-        std::fs::create_dir_all(dir)?;
-        vols.push(ContainerVolume {
-            name: name.clone(),
-            path: mount_point.path().to_string(),
-        });
-        if mount_point.is_public() {
-            public_vols.push(ContainerVolume {
-                name,
+fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Deployment configuration.
+#[derive(Default, Clone, Debug)]
+pub struct DeployOptions {
+    keep_volumes: bool,
+}
+
+impl DeployOptions {
+    /// Reuses volumes of the previous deployment mounted at the same paths,
+    /// instead of replacing them with empty ones.
+    pub fn with_keep_volumes(mut self, keep_volumes: bool) -> Self {
+        self.keep_volumes = keep_volumes;
+        self
+    }
+
+    /// Deploys application image, replacing previous deployment of the work dir.
+    pub fn deploy(self, workdir: &Path, path: &Path) -> anyhow::Result<DeployResult> {
+        let mut image = Image::from_path(path)?;
+        let manifest: Manifest = image.get_json(MANIFEST_FILE)?;
+        let previous = Deployment::load_existing(workdir)?;
+        let previous_vols = match &previous {
+            Some(previous) if self.keep_volumes => previous.vols(),
+            _ => Vec::new(),
+        };
+
+        let mut vols = Vec::new();
+        let mut public_vols = Vec::new();
+        let mut keystore = None;
+        if let Some(path) = manifest
+            .quotas
+            .keys()
+            .find(|path| !manifest.mount_points.iter().any(|m| m.path() == *path))
+        {
+            anyhow::bail!("quota for unknown mount point: {}", path);
+        }
+        for mount_point in manifest.mount_points {
+            let quota = match manifest.quotas.get(mount_point.path()) {
+                Some(quota) => Some(
+                    quota
+                        .bytes()
+                        .with_context(|| format!("quota of {}", mount_point.path()))?,
+                ),
+                None => None,
+            };
+            let name = previous_vols
+                .iter()
+                .find(|vol| vol.path == mount_point.path())
+                .map(|vol| vol.name.clone())
+                .unwrap_or_else(|| format!("vol-{}", uuid::Uuid::new_v4()));
+            let dir = workdir.join(&name);
+            std::fs::create_dir_all(dir)?;
+            vols.push(Volume {
+                name: name.clone(),
                 path: mount_point.path().to_string(),
-            })
+                mode: mount_point.mode(),
+                quota,
+            });
+            if mount_point.is_public() {
+                public_vols.push(ContainerVolume {
+                    name,
+                    path: mount_point.path().to_string(),
+                })
+            } else if keystore.is_none() {
+                keystore = Some(name);
+            }
+        }
+        let deployment = Deployment {
+            name: manifest.name,
+            entry_points: manifest.entry_points,
+            main: manifest.main,
+            image_path: path.to_path_buf(),
+            vols,
+            keystore,
+        };
+
+        if let Some(previous) = previous {
+            previous.remove_vols_unused_by(workdir, &deployment)?;
         }
+        deployment.save(workdir)?;
+
+        Ok(DeployResult {
+            valid: Ok("valid".to_string()),
+            vols: public_vols,
+            start_mode: StartMode::Blocking,
+        })
     }
-    let deployment = Deployment {
-        entry_points: manifest.entry_points,
-        main: manifest.main,
-        image_path: path.to_path_buf(),
-        vols,
-    };
-
-    deployment.save(workdir)?;
-
-    Ok(DeployResult {
-        valid: Ok("valid".to_string()),
-        vols: public_vols,
-        start_mode: StartMode::Blocking,
-    })
+}
+
+/// Deploys application image, replacing previous deployment of the work dir.
+pub fn deploy(workdir: &Path, path: &Path) -> anyhow::Result<DeployResult> {
+    DeployOptions::default().deploy(workdir, path)
+}
+
+/// Removes volumes, deploy file and invocation counter from the work dir.
+///
+/// Succeeds when the work dir is not deployed. Modules are compiled on every start,
+/// there is no module cache to clean up.
+pub fn undeploy(workdir: &Path) -> anyhow::Result<()> {
+    if let Some(deployment) = Deployment::load_existing(workdir)? {
+        for vol in &deployment.vols {
+            remove_dir(&workdir.join(&vol.name))?;
+        }
+    }
+    for file in &[INVOCATION_COUNTER_FILE, DEPLOY_FILE] {
+        match std::fs::remove_file(workdir.join(file)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -232,7 +405,8 @@ mod test {
   "name": "MyApp",
   "runtime": "aswasm",
   "main": {
-    "wasm-path": "app.wasm"
+    "wasm-path": "app.wasm",
+    "abi": "0.18"
   },
   "entry-points": {
     "init": {
@@ -280,6 +454,53 @@ mod test {
   }
 }"#;
         let m: Manifest = serde_json::from_str(json).unwrap();
+        assert_eq!(m.main.abi, Some(AbiVersion::V0_18));
         eprintln!("{:?}", m)
     }
+
+    fn write_image(path: &Path, mount_points: &str) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.start_file(MANIFEST_FILE, zip::write::FileOptions::default())
+            .unwrap();
+        write!(
+            zip,
+            r#"{{"id": "app", "name": "app", "runtime": "aswasm",
+                "main": {{"wasm-path": "app.wasm"}}, "mount-points": {}}}"#,
+            mount_points
+        )
+        .unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_redeploy() {
+        let dir = tempfile::tempdir().unwrap();
+        let work_dir = dir.path().join("work");
+        std::fs::create_dir(&work_dir).unwrap();
+        let image = dir.path().join("app.zip");
+        write_image(&image, r#"[{"rw": "/data"}, {"private": "/keys"}]"#);
+
+        deploy(&work_dir, &image).unwrap();
+        let first = Deployment::load(&work_dir).unwrap();
+        let keep = DeployOptions::default().with_keep_volumes(true);
+        keep.clone().deploy(&work_dir, &image).unwrap();
+        let names = |d: &Deployment| d.vols.iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&Deployment::load(&work_dir).unwrap()), names(&first));
+
+        write_image(&image, r#"[{"rw": "/data"}]"#);
+        keep.deploy(&work_dir, &image).unwrap();
+        let second = Deployment::load(&work_dir).unwrap();
+        assert_eq!(second.vols[0].name, first.vols[0].name);
+        assert!(!work_dir.join(&first.vols[1].name).exists());
+
+        deploy(&work_dir, &image).unwrap();
+        let third = Deployment::load(&work_dir).unwrap();
+        assert_ne!(third.vols[0].name, first.vols[0].name);
+        assert!(!work_dir.join(&first.vols[0].name).exists());
+
+        undeploy(&work_dir).unwrap();
+        assert_eq!(std::fs::read_dir(&work_dir).unwrap().count(), 0);
+        undeploy(&work_dir).unwrap();
+    }
 }
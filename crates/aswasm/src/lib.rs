@@ -3,13 +3,34 @@
 
 mod deploy;
 pub mod image;
+mod options;
 pub mod runtime;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod service;
 
-pub use deploy::deploy;
+pub use deploy::{deploy, undeploy, DeployOptions};
+pub use options::RuntimeOptions;
+pub use runtime::ActivityInfo;
+pub use service::RunOutcome;
 use std::path::Path;
 
-pub fn start(work_dir: &Path) -> anyhow::Result<()> {
-    let mut runtime = tokio::runtime::Builder::new().basic_scheduler().build()?;
-    runtime.block_on(service::start(work_dir))
+pub fn start(work_dir: &Path, activity: ActivityInfo) -> anyhow::Result<()> {
+    RuntimeOptions::default().start(work_dir, activity)
+}
+
+/// Runs single entry point and returns its return code with volume usage.
+pub fn run(
+    work_dir: &Path,
+    activity: ActivityInfo,
+    entry_point: &str,
+    args: Vec<String>,
+) -> anyhow::Result<RunOutcome> {
+    RuntimeOptions::default().run(work_dir, activity, entry_point, args)
+}
+
+/// Serves deployed application over HTTP, see [`server::serve`].
+#[cfg(feature = "server")]
+pub fn serve(work_dir: &Path, activity: ActivityInfo, bind: &str) -> anyhow::Result<()> {
+    RuntimeOptions::default().serve(work_dir, activity, bind)
 }
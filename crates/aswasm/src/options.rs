@@ -0,0 +1,169 @@
+use crate::runtime::{ActivityInfo, IoLimits, DEFAULT_MAX_FDS};
+use crate::service::{self, RunOutcome};
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+use ya_runtime_common::parse_size;
+
+const MAX_MEM_VAR: &str = "YA_RUNTIME_ASWASM_MAX_MEM";
+const GUARD_SIZE_VAR: &str = "YA_RUNTIME_ASWASM_GUARD_SIZE";
+const MAX_FDS_VAR: &str = "YA_RUNTIME_ASWASM_MAX_FDS";
+const MAX_FILE_SIZE_VAR: &str = "YA_RUNTIME_ASWASM_MAX_FILE_SIZE";
+const MAX_VOLUME_SIZE_VAR: &str = "YA_RUNTIME_ASWASM_MAX_VOLUME_SIZE";
+const OPTIMIZE_VAR: &str = "YA_RUNTIME_ASWASM_OPT";
+const TIMEOUT_VAR: &str = "YA_RUNTIME_ASWASM_TIMEOUT";
+
+/// Default limit of guest linear memory, 100 MiB.
+pub(crate) const DEFAULT_MAX_MEMORY: u64 = 100 << 20;
+pub(crate) const DEFAULT_GUARD_SIZE: u64 = 0x1_0000;
+
+/// aswasm runtime configuration.
+#[derive(Default, Clone, Debug)]
+pub struct RuntimeOptions {
+    pub(crate) max_memory: Option<u64>,
+    pub(crate) guard_size: Option<u64>,
+    pub(crate) max_fds: Option<usize>,
+    pub(crate) max_file_size: Option<u64>,
+    pub(crate) max_volume_size: Option<u64>,
+    pub(crate) optimize: Option<bool>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl RuntimeOptions {
+    /// Initializes runtime options from environment variables.
+    ///
+    /// * `YA_RUNTIME_ASWASM_MAX_MEM` - maximum memory size. (supported formats 100m, 1.2g)
+    /// * `YA_RUNTIME_ASWASM_GUARD_SIZE` - memory guard size.
+    /// * `YA_RUNTIME_ASWASM_MAX_FDS` - maximum number of open files.
+    /// * `YA_RUNTIME_ASWASM_MAX_FILE_SIZE` - maximum size of a file written by the guest.
+    /// * `YA_RUNTIME_ASWASM_MAX_VOLUME_SIZE` - maximum size of files in a volume.
+    /// * `YA_RUNTIME_ASWASM_OPT` - optimization. (0|no for no optimalization), (1|yes)
+    /// * `YA_RUNTIME_ASWASM_TIMEOUT` - maximum execution time of an invocation, in seconds.
+    ///
+    pub fn from_env() -> Result<Self> {
+        Self::default().with_env()
+    }
+
+    /// Overrides options with the ones set in environment variables, see [`Self::from_env`].
+    pub fn with_env(mut self) -> Result<Self> {
+        fn parse<T>(env_var: &str, parse: impl FnOnce(&str) -> Result<T>) -> Result<Option<T>> {
+            match env::var(env_var) {
+                Ok(value) => parse(value.trim())
+                    .map(Some)
+                    .map_err(|e| anyhow!("invalid value ({}) for {}: {}", value, env_var, e)),
+                Err(_) => Ok(None),
+            }
+        }
+        fn parse_bool(value: &str) -> Result<bool> {
+            match value {
+                "1" | "yes" => Ok(true),
+                "0" | "no" => Ok(false),
+                _ => bail!("0|1|no|yes expected"),
+            }
+        }
+
+        if let Some(max_memory) = parse(MAX_MEM_VAR, parse_size)? {
+            self.max_memory = Some(max_memory);
+        }
+        if let Some(guard_size) = parse(GUARD_SIZE_VAR, parse_size)? {
+            self.guard_size = Some(guard_size);
+        }
+        if let Some(max_fds) = parse(MAX_FDS_VAR, |value| Ok(value.parse()?))? {
+            self.max_fds = Some(max_fds);
+        }
+        if let Some(max_file_size) = parse(MAX_FILE_SIZE_VAR, parse_size)? {
+            self.max_file_size = Some(max_file_size);
+        }
+        if let Some(max_volume_size) = parse(MAX_VOLUME_SIZE_VAR, parse_size)? {
+            self.max_volume_size = Some(max_volume_size);
+        }
+        if let Some(optimize) = parse(OPTIMIZE_VAR, parse_bool)? {
+            self.optimize = Some(optimize);
+        }
+        if let Some(secs) = parse(TIMEOUT_VAR, |value| Ok(value.parse()?))? {
+            self.timeout = Some(Duration::from_secs(secs));
+        }
+        Ok(self)
+    }
+
+    /// Configures the maximum size, in bytes, of the guest linear memory.
+    pub fn with_max_memory(mut self, max_memory: impl Into<Option<u64>>) -> Self {
+        self.max_memory = max_memory.into();
+        self
+    }
+
+    /// Configures size of the guard region after guest linear memory.
+    pub fn with_guard_size(mut self, guard_size: impl Into<Option<u64>>) -> Self {
+        self.guard_size = guard_size.into();
+        self
+    }
+
+    /// Limits number of files open by the guest at the same time.
+    pub fn with_max_fds(mut self, max_fds: impl Into<Option<usize>>) -> Self {
+        self.max_fds = max_fds.into();
+        self
+    }
+
+    /// Limits size of files written by the guest.
+    pub fn with_max_file_size(mut self, max_file_size: impl Into<Option<u64>>) -> Self {
+        self.max_file_size = max_file_size.into();
+        self
+    }
+
+    /// Limits size of files in each volume, lowers quotas set in the manifest.
+    pub fn with_max_volume_size(mut self, max_volume_size: impl Into<Option<u64>>) -> Self {
+        self.max_volume_size = max_volume_size.into();
+        self
+    }
+
+    /// Changes default optimization level.
+    ///
+    /// * `true` - optimization for speed.
+    /// * `false` - no optimization.
+    ///
+    pub fn with_optimize(mut self, optimize: bool) -> Self {
+        self.optimize = Some(optimize);
+        self
+    }
+
+    /// Limits execution time of a single entry point invocation.
+    pub fn with_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    pub(crate) fn io_limits(&self) -> IoLimits {
+        IoLimits {
+            max_fds: self.max_fds.unwrap_or(DEFAULT_MAX_FDS),
+            max_file_size: self.max_file_size,
+            max_volume_size: self.max_volume_size,
+        }
+    }
+
+    /// Starts runtime service for the deployed application.
+    pub fn start(self, work_dir: &Path, activity: ActivityInfo) -> Result<()> {
+        let mut runtime = tokio::runtime::Builder::new().basic_scheduler().build()?;
+        runtime.block_on(service::start(work_dir, activity, self))
+    }
+
+    /// Runs single entry point and returns its return code with volume usage.
+    pub fn run(
+        self,
+        work_dir: &Path,
+        activity: ActivityInfo,
+        entry_point: &str,
+        args: Vec<String>,
+    ) -> Result<RunOutcome> {
+        service::run(work_dir, activity, self, entry_point, args)
+    }
+
+    /// Serves deployed application over HTTP, see [`crate::server::serve`].
+    #[cfg(feature = "server")]
+    pub fn serve(self, work_dir: &Path, activity: ActivityInfo, bind: &str) -> Result<()> {
+        let work_dir = work_dir.to_owned();
+        let bind = bind.to_owned();
+        actix_web::rt::System::new("aswasm-server")
+            .block_on(async move { crate::server::serve(work_dir, activity, self, &bind).await })
+    }
+}
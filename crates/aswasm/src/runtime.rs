@@ -1,43 +1,54 @@
 use secp256k1::{PublicKey, SecretKey};
-use std::convert::TryFrom;
-use wasmtime::{Caller, Func, Instance, Memory, Trap};
+use wasmtime::{Caller, Extern, Func, Instance, Memory, Trap};
+mod aead;
+mod context;
+mod ed25519;
 mod eth;
 mod io;
+mod keystore;
+mod logging;
+mod rtti;
+mod secp256r1;
 
-const ARRAYBUFFER_ID: u32 = 0;
-const STRING_ID: u32 = 1;
+pub use rtti::AbiVersion;
+use rtti::Layout;
 
 type Result<T> = std::result::Result<T, Trap>;
 
 pub struct AsMem {
     mem: Memory,
+    layout: Option<Layout>,
 }
 
-impl<'a> TryFrom<Caller<'a>> for AsMem {
-    type Error = Trap;
+impl AsMem {
+    /// `abi` is the runtime ABI pinned in the manifest, with `None` it is detected
+    /// from module exports.
+    pub fn for_caller(caller: &Caller, abi: Option<AbiVersion>) -> Result<Self> {
+        Self::resolve(|name| caller.get_export(name), abi)
+    }
 
-    fn try_from(value: Caller<'a>) -> Result<Self> {
-        Self::for_caller(&value)
+    pub fn for_instance(instance: &Instance, abi: Option<AbiVersion>) -> Result<Self> {
+        Self::resolve(|name| instance.get_export(name), abi)
     }
-}
 
-impl AsMem {
-    pub fn for_caller(caller: &Caller) -> Result<Self> {
-        let mem = caller
-            .get_export("memory")
+    fn resolve(exports: impl Fn(&str) -> Option<Extern>, abi: Option<AbiVersion>) -> Result<Self> {
+        let mem = exports("memory")
             .ok_or_else(|| Trap::new("missing memory export"))?
             .into_memory()
             .ok_or_else(|| Trap::new("wrong object exported as \"memory\""))?;
-        Ok(Self { mem })
+        let layout = Layout::resolve(&exports, &mem, abi)?;
+        Ok(Self { mem, layout })
     }
 
-    pub fn for_instance(instance: &Instance) -> Result<Self> {
-        let mem = instance
-            .get_export("memory")
-            .ok_or_else(|| Trap::new("missing memory export"))?
-            .into_memory()
-            .ok_or_else(|| Trap::new("wrong object exported as \"memory\""))?;
-        Ok(Self { mem })
+    /// AssemblyScript runtime ABI of the module.
+    pub fn abi_version(&self) -> Option<AbiVersion> {
+        self.layout.as_ref().map(|layout| layout.version)
+    }
+
+    fn layout(&self) -> Result<&Layout> {
+        self.layout.as_ref().ok_or_else(|| {
+            Trap::new("missing AssemblyScript runtime exports (compile with --exportRuntime)")
+        })
     }
 
     pub fn decode_str(&self, ptr: i32) -> Result<String> {
@@ -52,96 +63,130 @@ impl AsMem {
     }
 
     pub fn decode_secret(&self, ptr: i32) -> Result<SecretKey> {
-        unsafe { SecretKey::parse_slice(self.get_ptr(ptr)?).map_err(|e| Trap::new(e.to_string())) }
+        unsafe { SecretKey::parse_slice(self.get_data(ptr)?).map_err(|e| Trap::new(e.to_string())) }
     }
 
+    /// Decodes bytes of an `ArrayBuffer`, `Uint8Array` or `StaticArray<u8>` object.
     pub fn decode<T, F: FnOnce(&[u8]) -> Result<T>>(&self, ptr: i32, extractor: F) -> Result<T> {
-        unsafe { extractor(self.get_ptr(ptr)?) }
+        unsafe { extractor(self.get_data(ptr)?) }
     }
 
     pub fn decode_hash(&self, ptr: i32) -> Result<eth::EthHash> {
         unsafe {
-            eth::EthHash::parse_slice(self.get_ptr(ptr)?)
+            eth::EthHash::parse_slice(self.get_data(ptr)?)
                 .map_err(|e| Trap::new(format!("invalid message hash: {}", e)))
         }
     }
 
     pub fn decode_pubkey(&self, ptr: i32) -> Result<PublicKey> {
         unsafe {
-            PublicKey::parse_slice(self.get_ptr(ptr)?, None).map_err(|e| Trap::new(e.to_string()))
+            PublicKey::parse_slice(self.get_data(ptr)?, None).map_err(|e| Trap::new(e.to_string()))
+        }
+    }
+
+    fn view_data(&self, ptr: i32) -> Result<Option<(usize, usize)>> {
+        match &self.layout {
+            Some(layout) => layout.view_data(&self.mem, ptr as usize),
+            None => Ok(None),
+        }
+    }
+
+    unsafe fn get_data(&self, ptr: i32) -> Result<&[u8]> {
+        match self.view_data(ptr)? {
+            Some((start, len)) => self
+                .mem
+                .data_unchecked()
+                .get(start..start + len)
+                .ok_or_else(|| Trap::new(format!("invalid array view: {}", ptr))),
+            None => self.get_ptr(ptr),
+        }
+    }
+
+    unsafe fn get_mut_data(&mut self, ptr: i32) -> Result<&mut [u8]> {
+        match self.view_data(ptr)? {
+            Some((start, len)) => self
+                .mem
+                .data_unchecked_mut()
+                .get_mut(start..start + len)
+                .ok_or_else(|| Trap::new(format!("invalid array view: {}", ptr))),
+            None => self.get_mut_ptr(ptr),
         }
     }
 
     unsafe fn get_ptr(&self, ptr: i32) -> Result<&[u8]> {
-        let ptr = ptr as usize;
         let m = self.mem.data_unchecked();
-        if ptr < 4 {
-            return Err(Trap::new(format!("invalid allocation pointer: {}", ptr)));
-        }
-        let b = &m[(ptr - 4)..ptr];
-        let size = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
-        Ok(&m[ptr..(ptr + size)])
+        let range = object_range(m, ptr)?;
+        Ok(&m[range])
     }
 
     unsafe fn get_mut_ptr(&mut self, ptr: i32) -> Result<&mut [u8]> {
-        let ptr = ptr as usize;
         let m = self.mem.data_unchecked_mut();
-        if ptr < 4 {
-            return Err(Trap::new(format!("invalid allocation pointer: {}", ptr)));
-        }
-        let b = &m[(ptr - 4)..ptr];
-        let size = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
-        Ok(&mut m[ptr..(ptr + size)])
+        let range = object_range(m, ptr)?;
+        Ok(&mut m[range])
+    }
+}
+
+fn object_range(m: &[u8], ptr: i32) -> Result<std::ops::Range<usize>> {
+    let ptr = ptr as usize;
+    if ptr < 4 || ptr > m.len() {
+        return Err(Trap::new(format!("invalid allocation pointer: {}", ptr)));
+    }
+    let b = &m[(ptr - 4)..ptr];
+    let size = u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    if ptr + size > m.len() {
+        return Err(Trap::new(format!("invalid allocation pointer: {}", ptr)));
     }
+    Ok(ptr..(ptr + size))
+}
+
+enum Gc {
+    /// Reference counting runtime, objects handed to the guest must be retained.
+    Rc { retain: Func },
+    /// Tracing runtime, objects must be pinned while other allocations are made.
+    Tracing { pin: Func, unpin: Func },
 }
 
 pub struct Allocator {
     mem: AsMem,
     f_new: Func,
-    f_retain: Func,
+    gc: Gc,
 }
 
 impl Allocator {
-    pub fn for_caller(caller: &Caller) -> Result<Self> {
-        let mem = AsMem::for_caller(caller)?;
-        let f_new = caller
-            .get_export("__new")
-            .ok_or_else(|| Trap::new("Missing '__new' export"))?
-            .into_func()
-            .ok_or_else(|| Trap::new("invalid __new"))?;
-        let f_retain = caller
-            .get_export("__retain")
-            .ok_or_else(|| Trap::new("Missing '__retain' export"))?
-            .into_func()
-            .ok_or_else(|| Trap::new("invalid __retain"))?;
-
-        Ok(Self {
-            mem,
-            f_new,
-            f_retain,
+    pub fn for_caller(caller: &Caller, abi: Option<AbiVersion>) -> Result<Self> {
+        Self::resolve(AsMem::for_caller(caller, abi)?, |name| {
+            caller.get_export(name)
         })
     }
 
-    pub fn for_instance(caller: &Instance) -> Result<Self> {
-        let mem = AsMem::for_instance(caller)?;
-        let f_new = caller
-            .get_export("__new")
-            .ok_or_else(|| Trap::new("Missing '__new' export"))?
-            .into_func()
-            .ok_or_else(|| Trap::new("invalid __new"))?;
-        let f_retain = caller
-            .get_export("__retain")
-            .ok_or_else(|| Trap::new("Missing '__retain' export"))?
-            .into_func()
-            .ok_or_else(|| Trap::new("invalid __retain"))?;
-
-        Ok(Self {
-            mem,
-            f_new,
-            f_retain,
+    pub fn for_instance(instance: &Instance, abi: Option<AbiVersion>) -> Result<Self> {
+        Self::resolve(AsMem::for_instance(instance, abi)?, |name| {
+            instance.get_export(name)
         })
     }
 
+    fn resolve(mem: AsMem, exports: impl Fn(&str) -> Option<Extern>) -> Result<Self> {
+        let func = |name: &str| {
+            exports(name)
+                .ok_or_else(|| Trap::new(format!("Missing '{}' export", name)))?
+                .into_func()
+                .ok_or_else(|| Trap::new(format!("invalid {}", name)))
+        };
+        let f_new = func("__new")?;
+        let gc = if mem.layout()?.version.is_tracing() {
+            Gc::Tracing {
+                pin: func("__pin")?,
+                unpin: func("__unpin")?,
+            }
+        } else {
+            Gc::Rc {
+                retain: func("__retain")?,
+            }
+        };
+
+        Ok(Self { mem, f_new, gc })
+    }
+
     pub fn new_bytes_int(&mut self, bytes: &[u8], type_id: u32) -> Result<i32> {
         let ptr: i32 = self.f_new.get2()?(bytes.len() as i32, type_id)?;
         unsafe {
@@ -151,12 +196,14 @@ impl Allocator {
     }
 
     pub fn new_bytes(&mut self, bytes: &[u8]) -> Result<i32> {
-        self.new_bytes_int(bytes, ARRAYBUFFER_ID)
+        let type_id = self.mem.layout()?.array_buffer_id;
+        self.new_bytes_int(bytes, type_id)
     }
 
     pub fn new_string(&mut self, s: &str) -> Result<i32> {
         let v: Vec<u16> = s.encode_utf16().collect();
-        let ptr: i32 = self.f_new.get2()?(v.len() as u32 * 2u32, STRING_ID)?;
+        let type_id = self.mem.layout()?.string_id;
+        let ptr: i32 = self.f_new.get2()?(v.len() as u32 * 2u32, type_id)?;
         unsafe {
             for (chunk, [c0, c1]) in self
                 .mem
@@ -175,10 +222,39 @@ impl Allocator {
         self.mem.mem.data_size() as usize
     }
 
+    /// Transfers ownership of a newly allocated object to the guest.
     pub fn retain(&self, ptr: i32) -> Result<i32> {
-        self.f_retain.get1()?(ptr)
+        match &self.gc {
+            Gc::Rc { retain } => retain.get1()?(ptr),
+            Gc::Tracing { .. } => Ok(ptr),
+        }
+    }
+
+    /// Protects object from being collected by allocations that follow.
+    pub fn pin(&self, ptr: i32) -> Result<i32> {
+        match &self.gc {
+            Gc::Rc { .. } => Ok(ptr),
+            Gc::Tracing { pin, .. } => pin.get1()?(ptr),
+        }
+    }
+
+    pub fn unpin(&self, ptr: i32) -> Result<()> {
+        match &self.gc {
+            Gc::Rc { .. } => Ok(()),
+            Gc::Tracing { unpin, .. } => unpin.get1()?(ptr),
+        }
     }
 }
 
+pub use aead::link_aead;
+pub use context::{
+    link_context, ActivityInfo, ContextFactory, ContextGuard, ContextHandle, InvocationContext,
+};
+pub use ed25519::link_ed25519;
 pub use eth::link_eth;
-pub use io::link_io;
+pub use io::{
+    link_io, AccessMode, FdState, IoError, IoLimits, Volume, VolumeUsage, DEFAULT_MAX_FDS,
+};
+pub use keystore::{link_keystore, Keystore, KEYSTORE_KEY_ENV};
+pub use logging::{link_logging, LogConfig, LOG_LEVEL_ENV, LOG_STDERR_ENV};
+pub use secp256r1::link_p256;
@@ -0,0 +1,178 @@
+use super::{AbiVersion, Allocator, AsMem};
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use rand::Rng;
+use wasmtime::{Caller, Linker, Trap};
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+fn decode_key(mem: &AsMem, ptr: i32) -> Result<[u8; KEY_SIZE], Trap> {
+    mem.decode(ptr, |bytes| {
+        if bytes.len() != KEY_SIZE {
+            return Err(Trap::new(format!(
+                "invalid key size: {}, expected {}",
+                bytes.len(),
+                KEY_SIZE
+            )));
+        }
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(bytes);
+        Ok(key)
+    })
+}
+
+/// Encrypts message with a random nonce, returns `nonce || ciphertext || tag`.
+pub(super) fn seal<C: Aead<NonceSize = U12>>(
+    cipher: C,
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, Trap> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(GenericArray::from_slice(&nonce), Payload { msg, aad })
+        .map_err(|_| Trap::new("encryption failed"))?;
+    let mut output = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+/// Decrypts output of [`seal`], returns `None` when message was not authenticated.
+pub(super) fn open<C: Aead<NonceSize = U12>>(
+    cipher: C,
+    sealed: &[u8],
+    aad: &[u8],
+) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_SIZE {
+        return None;
+    }
+    let (nonce, msg) = sealed.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), Payload { msg, aad })
+        .ok()
+}
+
+fn link_cipher<C: Aead<NonceSize = U12> + NewAead + 'static>(
+    module: &str,
+    name: &str,
+    linker: &mut Linker,
+    abi: Option<AbiVersion>,
+) -> anyhow::Result<()> {
+    linker.func(
+        module,
+        &format!("aead.{}Seal", name),
+        move |caller: Caller, key_ptr: i32, msg_ptr: i32, aad_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let cipher = C::new(GenericArray::from_slice(&decode_key(&mem, key_ptr)?));
+            let sealed = mem.decode(msg_ptr, |msg| {
+                mem.decode(aad_ptr, |aad| seal(cipher, msg, aad))
+            })?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(&sealed)?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    linker.func(
+        module,
+        &format!("aead.{}Open", name),
+        move |caller: Caller, key_ptr: i32, sealed_ptr: i32, aad_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let cipher = C::new(GenericArray::from_slice(&decode_key(&mem, key_ptr)?));
+            let opened = mem.decode(sealed_ptr, |sealed| {
+                mem.decode(aad_ptr, |aad| Ok(open(cipher, sealed, aad)))
+            })?;
+            match opened {
+                Some(plaintext) => {
+                    let mut allocator = Allocator::for_caller(&caller, abi)?;
+                    let ptr = allocator.new_bytes(&plaintext)?;
+                    allocator.retain(ptr)
+                }
+                None => Ok(0),
+            }
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Registers `aead.*` host functions.
+///
+/// Sealed messages are prefixed with a random 12 bytes nonce. Open functions return `null`
+/// when message fails authentication.
+pub fn link_aead(module: &str, linker: &mut Linker, abi: Option<AbiVersion>) -> anyhow::Result<()> {
+    //export function aes256GcmSeal(key: ArrayBuffer, plaintext: ArrayBuffer, aad: ArrayBuffer): ArrayBuffer;
+    //export function aes256GcmOpen(key: ArrayBuffer, sealed: ArrayBuffer, aad: ArrayBuffer): ArrayBuffer | null;
+    link_cipher::<Aes256Gcm>(module, "aes256Gcm", linker, abi)?;
+    //export function chacha20Poly1305Seal(key: ArrayBuffer, plaintext: ArrayBuffer, aad: ArrayBuffer): ArrayBuffer;
+    //export function chacha20Poly1305Open(key: ArrayBuffer, sealed: ArrayBuffer, aad: ArrayBuffer): ArrayBuffer | null;
+    link_cipher::<ChaCha20Poly1305>(module, "chacha20Poly1305", linker, abi)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Opens a published vector laid out as `nonce || ciphertext || tag`.
+    fn check_vector<C: Aead<NonceSize = U12> + NewAead>(
+        key: &str,
+        nonce: &str,
+        aad: &str,
+        plaintext: &[u8],
+        ciphertext: &str,
+    ) {
+        let new_cipher = || C::new(GenericArray::from_slice(&hex::decode(key).unwrap()));
+        let aad = hex::decode(aad).unwrap();
+        let mut sealed = hex::decode(format!("{}{}", nonce, ciphertext)).unwrap();
+        assert_eq!(open(new_cipher(), &sealed, &aad).unwrap(), plaintext);
+
+        let resealed = seal(new_cipher(), plaintext, &aad).unwrap();
+        assert_eq!(resealed.len(), sealed.len());
+        assert_eq!(open(new_cipher(), &resealed, &aad).unwrap(), plaintext);
+
+        sealed[NONCE_SIZE] ^= 1;
+        assert!(open(new_cipher(), &sealed, &aad).is_none());
+        assert!(open(new_cipher(), &sealed[..NONCE_SIZE - 1], &aad).is_none());
+    }
+
+    /// McGrew & Viega, The Galois/Counter Mode of Operation, test case 16.
+    #[test]
+    fn test_aes256_gcm() {
+        check_vector::<Aes256Gcm>(
+            "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+            "cafebabefacedbaddecaf888",
+            "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+            &hex::decode(
+                "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95\
+                 956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+            )
+            .unwrap(),
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa8cb08e48\
+             590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+             76fc6ece0f4e1768cddf8853bb2d551b",
+        );
+    }
+
+    /// RFC 8439, section 2.8.2.
+    #[test]
+    fn test_chacha20_poly1305() {
+        check_vector::<ChaCha20Poly1305>(
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+            "070000004041424344454647",
+            "50515253c0c1c2c3c4c5c6c7",
+            b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip \
+              for the future, sunscreen would be it.",
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8c\
+             a9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c98\
+             03aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586ce\
+             c64b6116\
+             1ae10b594f09e26a7e902ecbd0600691",
+        );
+    }
+}
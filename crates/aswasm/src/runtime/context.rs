@@ -0,0 +1,178 @@
+use super::{AbiVersion, Allocator};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasmtime::{Caller, Linker, Trap};
+
+/// Identifiers of the activity the runtime was started for, as passed on the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ActivityInfo {
+    pub activity_id: Option<String>,
+    pub agreement_id: Option<String>,
+}
+
+/// Context of a single entry point invocation, visible to the guest as `ya.context()`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InvocationContext {
+    pub pid: u64,
+    pub entry_point: String,
+    /// Invocation counter, increasing for the lifetime of the deployment.
+    pub invocation: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activity_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agreement_id: Option<String>,
+    /// Invocation start, in milliseconds since unix epoch.
+    pub timestamp: u64,
+}
+
+/// Source of invocation contexts for a deployed application.
+///
+/// The counter is persisted in the work dir, so it never goes back when the
+/// runtime is restarted.
+pub struct ContextFactory {
+    activity: ActivityInfo,
+    counter: u64,
+    counter_path: Option<PathBuf>,
+}
+
+impl ContextFactory {
+    pub fn new(activity: ActivityInfo, counter_path: Option<PathBuf>) -> Self {
+        let counter = counter_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|counter| counter.trim().parse().ok())
+            .unwrap_or_default();
+        ContextFactory {
+            activity,
+            counter,
+            counter_path,
+        }
+    }
+
+    pub fn next(&mut self, pid: u64, entry_point: &str) -> InvocationContext {
+        self.counter += 1;
+        if let Some(path) = &self.counter_path {
+            if let Err(e) = std::fs::write(path, self.counter.to_string()) {
+                log::warn!("unable to save invocation counter: {}", e);
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        InvocationContext {
+            pid,
+            entry_point: entry_point.to_string(),
+            invocation: self.counter,
+            activity_id: self.activity.activity_id.clone(),
+            agreement_id: self.activity.agreement_id.clone(),
+            timestamp,
+        }
+    }
+}
+
+/// Context of the running invocation, shared with host functions.
+#[derive(Clone, Default)]
+pub struct ContextHandle(Rc<RefCell<Option<InvocationContext>>>);
+
+impl ContextHandle {
+    /// Makes context visible to the guest until the returned guard is dropped.
+    pub fn enter(&self, context: InvocationContext) -> ContextGuard {
+        *self.0.borrow_mut() = Some(context);
+        ContextGuard(self.clone())
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&InvocationContext) -> T) -> Option<T> {
+        self.0.borrow().as_ref().map(f)
+    }
+}
+
+pub struct ContextGuard(ContextHandle);
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        *(self.0).0.borrow_mut() = None;
+    }
+}
+
+/*
+export declare namespace ya {
+  // JSON: {pid, entryPoint, invocation, activityId?, agreementId?, timestamp},
+  // null outside of an entry point invocation.
+  export function context() : string | null;
+  export function invocation() : u64;
+  // invocation start, ms since unix epoch.
+  export function timestamp() : u64;
+}
+*/
+
+/// Registers `context`, `invocation` and `timestamp` host functions.
+pub fn link_context(
+    module: &str,
+    linker: &mut Linker,
+    abi: Option<AbiVersion>,
+    handle: ContextHandle,
+) -> anyhow::Result<()> {
+    {
+        let handle = handle.clone();
+        linker.func(
+            module,
+            "context",
+            move |caller: Caller| -> Result<i32, Trap> {
+                let json = match handle.with(serde_json::to_string) {
+                    Some(json) => json.map_err(|e| Trap::new(e.to_string()))?,
+                    None => return Ok(0),
+                };
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&json)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let handle = handle.clone();
+        linker.func(module, "invocation", move || -> Result<i64, Trap> {
+            Ok(handle
+                .with(|context| context.invocation as i64)
+                .unwrap_or_default())
+        })?;
+    }
+
+    linker.func(module, "timestamp", move || -> Result<i64, Trap> {
+        Ok(handle
+            .with(|context| context.timestamp as i64)
+            .unwrap_or_default())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counter_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("invocations");
+        let activity = ActivityInfo {
+            activity_id: Some("act-1".to_string()),
+            agreement_id: None,
+        };
+        let mut factory = ContextFactory::new(activity.clone(), Some(path.clone()));
+        assert_eq!(factory.next(1, "init").invocation, 1);
+        assert_eq!(factory.next(2, "run").invocation, 2);
+
+        let context = ContextFactory::new(activity, Some(path)).next(1, "run");
+        assert_eq!(context.invocation, 3);
+        let json: serde_json::Value = serde_json::to_value(&context).unwrap();
+        assert_eq!(json["activityId"], "act-1");
+        assert_eq!(json["entryPoint"], "run");
+        assert!(json.get("agreementId").is_none());
+    }
+}
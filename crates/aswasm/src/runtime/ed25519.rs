@@ -0,0 +1,146 @@
+use super::{AbiVersion, Allocator, AsMem};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+use std::convert::TryFrom;
+use wasmtime::{Caller, Linker, Trap};
+
+fn keypair(bytes: &[u8]) -> Result<Keypair, Trap> {
+    let secret = SecretKey::from_bytes(bytes)
+        .map_err(|e| Trap::new(format!("invalid ed25519 key: {}", e)))?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+fn decode_keypair(mem: &AsMem, ptr: i32) -> Result<Keypair, Trap> {
+    mem.decode(ptr, keypair)
+}
+
+/// Traps on malformed public key or signature, returns `false` when signature does not match.
+fn verify(public: &[u8], message: &[u8], signature: &[u8]) -> Result<bool, Trap> {
+    let public = PublicKey::from_bytes(public)
+        .map_err(|e| Trap::new(format!("invalid ed25519 public key: {}", e)))?;
+    let signature = Signature::try_from(signature)
+        .map_err(|e| Trap::new(format!("invalid ed25519 signature: {}", e)))?;
+    Ok(public.verify_strict(message, &signature).is_ok())
+}
+
+/// Registers `ed25519.*` host functions.
+///
+/// Malformed keys and signatures trap, `verify` returns `false` only for well-formed
+/// signatures that do not match.
+pub fn link_ed25519(
+    module: &str,
+    linker: &mut Linker,
+    abi: Option<AbiVersion>,
+) -> anyhow::Result<()> {
+    // returns 32 bytes secret key.
+    //export function newKey(): ArrayBuffer;
+    linker.func(
+        module,
+        "ed25519.newKey",
+        move |caller: Caller| -> Result<i32, Trap> {
+            let keypair = Keypair::generate(&mut rand::thread_rng());
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(keypair.secret.as_bytes())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    // returns 32 bytes public key.
+    //export function toPubKey(prvKey: ArrayBuffer): ArrayBuffer;
+    linker.func(
+        module,
+        "ed25519.toPubKey",
+        move |caller: Caller, prv_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let keypair = decode_keypair(&mem, prv_ptr)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(keypair.public.as_bytes())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    // returns 64 bytes signature.
+    //export function sign(prvKey: ArrayBuffer, message: ArrayBuffer): ArrayBuffer;
+    linker.func(
+        module,
+        "ed25519.sign",
+        move |caller: Caller, prv_ptr: i32, msg_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let keypair = decode_keypair(&mem, prv_ptr)?;
+            let signature = mem.decode(msg_ptr, |message| Ok(keypair.sign(message)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(&signature.to_bytes())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    //export function verify(pubKey: ArrayBuffer, message: ArrayBuffer, signature: ArrayBuffer): bool;
+    linker.func(
+        module,
+        "ed25519.verify",
+        move |caller: Caller, pub_ptr: i32, msg_ptr: i32, sig_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let valid = mem.decode(pub_ptr, |public| {
+                mem.decode(msg_ptr, |message| {
+                    mem.decode(sig_ptr, |signature| verify(public, message, signature))
+                })
+            })?;
+            Ok(valid as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// RFC 8032, section 7.1: secret key, public key, message, signature.
+    const RFC8032: &[(&str, &str, &str, &str)] = &[
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bac\
+             c61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e\
+             458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+    ];
+
+    #[test]
+    fn test_rfc8032() {
+        for (secret, public, message, signature) in RFC8032 {
+            let keypair = keypair(&hex::decode(secret).unwrap()).unwrap();
+            let message = hex::decode(message).unwrap();
+            assert_eq!(hex::encode(keypair.public.as_bytes()), *public);
+            assert_eq!(hex::encode(keypair.sign(&message).to_bytes()), *signature);
+            assert!(verify(
+                &hex::decode(public).unwrap(),
+                &message,
+                &hex::decode(signature).unwrap()
+            )
+            .unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_malformed() {
+        let (_, public, message, signature) = RFC8032[1];
+        let public = hex::decode(public).unwrap();
+        let message = hex::decode(message).unwrap();
+        let mut signature = hex::decode(signature).unwrap();
+
+        assert!(!verify(&public, b"other message", &signature).unwrap());
+        assert!(verify(&public[..31], &message, &signature).is_err());
+        assert!(verify(&public, &message, &signature[..63]).is_err());
+        signature[32] ^= 1;
+        assert!(!verify(&public, &message, &signature).unwrap());
+    }
+}
@@ -1,12 +1,21 @@
-use super::{Allocator, AsMem};
+use super::{AbiVersion, Allocator, AsMem};
 use secp256k1::SharedSecret;
 pub use secp256k1::{Error, Message, PublicKey, SecretKey};
+use serde::Deserialize;
+use serde_json::Value;
 use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Debug;
 use tiny_keccak::{Hasher, Keccak};
 use wasmtime::{Caller, Linker, Trap};
 
+pub mod abi;
+pub mod ecies;
+pub mod rlp;
+pub mod tx;
+pub mod typed_data;
+mod uint;
+
 pub struct EthHash([u8; 32]);
 
 impl EthHash {
@@ -21,6 +30,12 @@ impl EthHash {
         eth_hash_parts(&[prefix.as_ref(), msg_size.as_ref(), message])
     }
 
+    /// EIP-712 digest of a typed data document with `types`, `primaryType`,
+    /// `domain` and `message` fields.
+    pub fn typed_data(document: &Value) -> anyhow::Result<EthHash> {
+        typed_data::TypedData::deserialize(document)?.hash()
+    }
+
     pub fn build_with(signature: &str) -> EthHashBuilder {
         let sig = signature_hash(signature);
         let mut hasher = Keccak::v256();
@@ -198,12 +213,15 @@ impl RecoverableSignature {
     }
 }
 
-pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
+/// Registers `eth.*` host functions.
+///
+/// Byte arguments declared as `ArrayBuffer` also accept `Uint8Array` and `StaticArray<u8>`.
+pub fn link_eth(module: &str, linker: &mut Linker, abi: Option<AbiVersion>) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.newKey",
-        |caller: Caller| -> Result<i32, Trap> {
-            let mut a = Allocator::for_caller(&caller)?;
+        move |caller: Caller| -> Result<i32, Trap> {
+            let mut a = Allocator::for_caller(&caller, abi)?;
             let secret = secp256k1::SecretKey::random(&mut rand::thread_rng());
             let ptr = a.new_bytes(secret.serialize().as_ref())?;
             //eprintln!("heap: {}", a.size());
@@ -215,10 +233,10 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.prvToAddress",
-        |caller: Caller, ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let secret = mem.decode_secret(ptr)?;
-            let mut a = Allocator::for_caller(&caller)?;
+            let mut a = Allocator::for_caller(&caller, abi)?;
             let ptr = a.new_string(&secret.to_eth_address().to_hex_string())?;
             a.retain(ptr)?;
             Ok(ptr)
@@ -228,10 +246,10 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.pubToAddress",
-        |caller: Caller, ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let public_key = mem.decode_pubkey(ptr)?;
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let ptr = allocator.new_string(&public_key.to_eth_address().to_hex_string())?;
             allocator.retain(ptr)?;
             Ok(ptr)
@@ -242,12 +260,12 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.sign",
-        |caller: Caller, pk_ptr: i32, hash_ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, pk_ptr: i32, hash_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let pk = mem.decode_secret(pk_ptr)?;
             let hash = mem.decode_hash(hash_ptr)?;
             let bytes = hash.sign_by(&pk).serialize_rpc();
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let out_ptr = allocator.new_bytes(&bytes)?;
             allocator.retain(out_ptr)?;
             Ok(out_ptr)
@@ -258,10 +276,10 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.keccak256",
-        |caller: Caller, ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let hash = mem.decode(ptr, |slice| Ok(eth_hash_parts(&[slice])))?;
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let out_ptr = allocator.new_bytes(hash.as_ref())?;
             allocator.retain(out_ptr)?;
             Ok(out_ptr)
@@ -276,8 +294,8 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.ecrecover",
-        |caller: Caller, hash_ptr: i32, sig_ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, hash_ptr: i32, sig_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let hash = mem.decode_hash(hash_ptr)?;
             let sign = mem.decode(sig_ptr, |bytes| {
                 RecoverableSignature::from_bytes(bytes)
@@ -286,7 +304,7 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
             let pub_key = sign
                 .recover_pub_key(&hash)
                 .map_err(|e| Trap::new(e.to_string()))?;
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let out_ptr = allocator.new_bytes(pub_key.serialize().as_ref())?;
             allocator.retain(out_ptr)?;
             Ok(out_ptr)
@@ -296,10 +314,10 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.bytesToHex",
-        |caller: Caller, bytes_ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, bytes_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let hex_str = mem.decode(bytes_ptr, |bytes| Ok(hex::encode(bytes)))?;
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let out_ptr = allocator.new_string(&hex_str)?;
             allocator.retain(out_ptr)?;
             Ok(out_ptr)
@@ -310,18 +328,161 @@ pub fn link_eth(module: &str, linker: &mut Linker) -> anyhow::Result<()> {
     linker.func(
         module,
         "eth.sharedSecret",
-        |caller: Caller, prv_ptr: i32, pub_ptr: i32| -> Result<i32, Trap> {
-            let mem = AsMem::for_caller(&caller)?;
+        move |caller: Caller, prv_ptr: i32, pub_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
             let secret = mem.decode_secret(prv_ptr)?;
             let pubkey = mem.decode_pubkey(pub_ptr)?;
             let shared_secret: SharedSecret<sha2::Sha256> =
                 SharedSecret::new(&pubkey, &secret).map_err(|e| Trap::new(e.to_string()))?;
-            let mut allocator = Allocator::for_caller(&caller)?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
             let out_ptr = allocator.new_bytes(shared_secret.as_ref())?;
             allocator.retain(out_ptr)?;
             Ok(out_ptr)
         },
     )?;
 
+    // ECIES encryption compatible with Ethereum, see [`ecies`].
+    //export function encryptTo(pubKey: ArrayBuffer, data: ArrayBuffer): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.encryptTo",
+        move |caller: Caller, pub_ptr: i32, data_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let pubkey = mem.decode_pubkey(pub_ptr)?;
+            let blob = mem.decode(data_ptr, |data| {
+                ecies::encrypt(&pubkey, data).map_err(|e| Trap::new(e.to_string()))
+            })?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&blob)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // returns null when message can not be decrypted with the given key.
+    //export function decrypt(prvKey: ArrayBuffer, blob: ArrayBuffer): ArrayBuffer | null;
+    linker.func(
+        module,
+        "eth.decrypt",
+        move |caller: Caller, prv_ptr: i32, blob_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let secret = mem.decode_secret(prv_ptr)?;
+            let data = match mem.decode(blob_ptr, |blob| Ok(ecies::decrypt(&secret, blob)))? {
+                Ok(data) => data,
+                Err(_) => return Ok(0),
+            };
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&data)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // Encodes JSON document: arrays as lists, "0x" strings as bytes, numbers and decimal strings as integers.
+    //export function rlpEncode(json: string): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.rlpEncode",
+        move |caller: Caller, json_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let value = decode_json(&mem, json_ptr)?;
+            let bytes = rlp::Item::from_json(&value)
+                .map_err(|e| Trap::new(format!("rlp: {}", e)))?
+                .encode();
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&bytes)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // types - comma separated list, eg. "address,uint256,bytes".
+    // values - JSON array of values.
+    //export function abiEncode(types: string, values: string): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.abiEncode",
+        move |caller: Caller, types_ptr: i32, values_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let types = abi::ParamType::parse_list(&mem.decode_str(types_ptr)?)
+                .map_err(|e| Trap::new(format!("abi: {}", e)))?;
+            let values = decode_json_array(&mem, values_ptr)?;
+            let bytes =
+                abi::encode(&types, &values).map_err(|e| Trap::new(format!("abi: {}", e)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&bytes)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // signature - function declaration, eg. "transfer(address,uint256)".
+    //export function abiEncodeCall(signature: string, values: string): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.abiEncodeCall",
+        move |caller: Caller, sig_ptr: i32, values_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let signature = mem.decode_str(sig_ptr)?;
+            let values = decode_json_array(&mem, values_ptr)?;
+            let bytes = abi::encode_call(&signature, &values)
+                .map_err(|e| Trap::new(format!("abi: {}", e)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&bytes)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // Signs legacy (EIP-155), EIP-2930 or EIP-1559 transaction, see [`tx::TxRequest`].
+    // returns raw signed transaction.
+    //export function signTx(prvKey: ArrayBuffer, tx: string): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.signTx",
+        move |caller: Caller, prv_ptr: i32, tx_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let secret = mem.decode_secret(prv_ptr)?;
+            let tx: tx::TxRequest = serde_json::from_str(&mem.decode_str(tx_ptr)?)
+                .map_err(|e| Trap::new(format!("invalid transaction: {}", e)))?;
+            let bytes = tx
+                .sign(&secret)
+                .map_err(|e| Trap::new(format!("invalid transaction: {}", e)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(&bytes)?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
+    // EIP-712 digest of a JSON typed data document (types, primaryType, domain, message).
+    //export function typedDataHash(json: string): ArrayBuffer;
+    linker.func(
+        module,
+        "eth.typedDataHash",
+        move |caller: Caller, json_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let document = decode_json(&mem, json_ptr)?;
+            let hash = EthHash::typed_data(&document)
+                .map_err(|e| Trap::new(format!("invalid typed data: {}", e)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let out_ptr = allocator.new_bytes(hash.as_ref())?;
+            allocator.retain(out_ptr)?;
+            Ok(out_ptr)
+        },
+    )?;
+
     Ok(())
 }
+
+fn decode_json(mem: &AsMem, ptr: i32) -> Result<Value, Trap> {
+    serde_json::from_str(&mem.decode_str(ptr)?)
+        .map_err(|e| Trap::new(format!("invalid json: {}", e)))
+}
+
+fn decode_json_array(mem: &AsMem, ptr: i32) -> Result<Vec<Value>, Trap> {
+    match decode_json(mem, ptr)? {
+        Value::Array(values) => Ok(values),
+        _ => Err(Trap::new("expected json array of values")),
+    }
+}
@@ -0,0 +1,289 @@
+//! Contract ABI encoding of function arguments.
+
+use super::{signature_hash, uint};
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    Address,
+    Bool,
+    Uint(usize),
+    Int(usize),
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<ParamType>),
+    FixedArray(Box<ParamType>, usize),
+}
+
+impl ParamType {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if let Some(inner) = s.strip_suffix(']') {
+            let idx = inner
+                .rfind('[')
+                .ok_or_else(|| anyhow!("invalid type: {}", s))?;
+            let element = Box::new(Self::parse(&inner[..idx])?);
+            return Ok(match &inner[idx + 1..] {
+                "" => ParamType::Array(element),
+                n => ParamType::FixedArray(element, n.parse()?),
+            });
+        }
+        let size = |prefix: &str, default: usize| -> anyhow::Result<usize> {
+            match &s[prefix.len()..] {
+                "" => Ok(default),
+                n => n.parse().with_context(|| format!("invalid type: {}", s)),
+            }
+        };
+        Ok(match s {
+            "address" => ParamType::Address,
+            "bool" => ParamType::Bool,
+            "bytes" => ParamType::Bytes,
+            "string" => ParamType::String,
+            _ if s.starts_with("uint") => ParamType::Uint(check_bits(s, size("uint", 256)?)?),
+            _ if s.starts_with("int") => ParamType::Int(check_bits(s, size("int", 256)?)?),
+            _ if s.starts_with("bytes") => match size("bytes", 0)? {
+                n @ 1..=32 => ParamType::FixedBytes(n),
+                _ => bail!("invalid type: {}", s),
+            },
+            _ => bail!("unsupported type: {}", s),
+        })
+    }
+
+    /// Parses comma separated list of types.
+    pub fn parse_list(s: &str) -> anyhow::Result<Vec<Self>> {
+        if s.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        s.split(',').map(Self::parse).collect()
+    }
+
+    fn is_dynamic(&self) -> bool {
+        match self {
+            ParamType::Bytes | ParamType::String | ParamType::Array(_) => true,
+            ParamType::FixedArray(element, _) => element.is_dynamic(),
+            _ => false,
+        }
+    }
+
+    fn head_size(&self) -> usize {
+        match self {
+            ParamType::FixedArray(element, n) if !element.is_dynamic() => n * element.head_size(),
+            _ => 32,
+        }
+    }
+
+    pub(super) fn encode(&self, value: &Value, output: &mut Vec<u8>) -> anyhow::Result<()> {
+        match self {
+            ParamType::Address => {
+                let address = hex_bytes(value)?;
+                if address.len() != 20 {
+                    bail!("invalid address: {}", value);
+                }
+                output.extend_from_slice(&[0; 12]);
+                output.extend_from_slice(&address);
+            }
+            ParamType::Bool => match value {
+                Value::Bool(b) => output.extend_from_slice(&uint::from_u64(*b as u64)),
+                _ => bail!("expected bool, got {}", value),
+            },
+            ParamType::Uint(bits) => {
+                let n = uint::from_json(value)?;
+                if uint::bits(&n) > *bits {
+                    bail!("{} does not fit in {}", value, self);
+                }
+                output.extend_from_slice(&n);
+            }
+            ParamType::Int(bits) => {
+                let (negative, n) = match value {
+                    Value::Number(n) if n.is_i64() => {
+                        let n = n.as_i64().unwrap_or_default();
+                        (n < 0, uint::from_u64(n.wrapping_abs() as u64))
+                    }
+                    Value::String(s) if s.starts_with('-') => (true, uint::parse(&s[1..])?),
+                    _ => (false, uint::from_json(value)?),
+                };
+                // negative values reach one further: -2^(bits - 1)
+                let n_bits = uint::bits(&n);
+                let fits = n_bits < *bits
+                    || negative
+                        && n_bits == *bits
+                        && n.iter().map(|b| b.count_ones()).sum::<u32>() == 1;
+                if !fits {
+                    bail!("{} does not fit in {}", value, self);
+                }
+                output.extend_from_slice(&if negative { uint::negate(&n) } else { n });
+            }
+            ParamType::FixedBytes(size) => {
+                let bytes = hex_bytes(value)?;
+                if bytes.len() != *size {
+                    bail!("expected {} bytes, got {}", size, value);
+                }
+                pad_right(output, &bytes);
+            }
+            ParamType::Bytes => {
+                let bytes = hex_bytes(value)?;
+                output.extend_from_slice(&uint::from_u64(bytes.len() as u64));
+                pad_right(output, &bytes);
+            }
+            ParamType::String => match value {
+                Value::String(s) => {
+                    output.extend_from_slice(&uint::from_u64(s.len() as u64));
+                    pad_right(output, s.as_bytes());
+                }
+                _ => bail!("expected string, got {}", value),
+            },
+            ParamType::Array(element) => {
+                let values = array(value)?;
+                output.extend_from_slice(&uint::from_u64(values.len() as u64));
+                let types = vec![element.as_ref().clone(); values.len()];
+                output.extend(encode(&types, values)?);
+            }
+            ParamType::FixedArray(element, n) => {
+                let values = array(value)?;
+                if values.len() != *n {
+                    bail!("expected {} elements, got {}", n, values.len());
+                }
+                let types = vec![element.as_ref().clone(); *n];
+                output.extend(encode(&types, values)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamType::Address => f.write_str("address"),
+            ParamType::Bool => f.write_str("bool"),
+            ParamType::Uint(bits) => write!(f, "uint{}", bits),
+            ParamType::Int(bits) => write!(f, "int{}", bits),
+            ParamType::FixedBytes(size) => write!(f, "bytes{}", size),
+            ParamType::Bytes => f.write_str("bytes"),
+            ParamType::String => f.write_str("string"),
+            ParamType::Array(element) => write!(f, "{}[]", element),
+            ParamType::FixedArray(element, n) => write!(f, "{}[{}]", element, n),
+        }
+    }
+}
+
+/// Encodes values as a tuple of the given types.
+pub fn encode(types: &[ParamType], values: &[Value]) -> anyhow::Result<Vec<u8>> {
+    if types.len() != values.len() {
+        bail!("expected {} values, got {}", types.len(), values.len());
+    }
+    let heads_size: usize = types.iter().map(ParamType::head_size).sum();
+    let mut heads = Vec::with_capacity(heads_size);
+    let mut tails = Vec::new();
+    for (param_type, value) in types.iter().zip(values) {
+        if param_type.is_dynamic() {
+            heads.extend_from_slice(&uint::from_u64((heads_size + tails.len()) as u64));
+            param_type.encode(value, &mut tails)?;
+        } else {
+            param_type.encode(value, &mut heads)?;
+        }
+    }
+    heads.extend(tails);
+    Ok(heads)
+}
+
+/// Encodes function call data: 4 byte selector followed by arguments.
+///
+/// `signature` is a function declaration such as `transfer(address,uint256)`.
+pub fn encode_call(signature: &str, values: &[Value]) -> anyhow::Result<Vec<u8>> {
+    let open = signature
+        .find('(')
+        .filter(|_| signature.ends_with(')'))
+        .ok_or_else(|| anyhow!("invalid function signature: {}", signature))?;
+    let types = ParamType::parse_list(&signature[open + 1..signature.len() - 1])?;
+    let canonical = format!(
+        "{}({})",
+        signature[..open].trim(),
+        types
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let mut output = signature_hash(&canonical).as_ref()[..4].to_vec();
+    output.extend(encode(&types, values)?);
+    Ok(output)
+}
+
+fn check_bits(s: &str, bits: usize) -> anyhow::Result<usize> {
+    if bits == 0 || bits > 256 || bits % 8 != 0 {
+        bail!("invalid type: {}", s);
+    }
+    Ok(bits)
+}
+
+fn hex_bytes(value: &Value) -> anyhow::Result<Vec<u8>> {
+    match value.as_str() {
+        Some(s) => Ok(hex::decode(s.strip_prefix("0x").unwrap_or(s))?),
+        None => bail!("expected hex string, got {}", value),
+    }
+}
+
+fn array(value: &Value) -> anyhow::Result<&[Value]> {
+    value
+        .as_array()
+        .map(Vec::as_slice)
+        .ok_or_else(|| anyhow!("expected array, got {}", value))
+}
+
+fn pad_right(output: &mut Vec<u8>, bytes: &[u8]) {
+    output.extend_from_slice(bytes);
+    let padding = (32 - bytes.len() % 32) % 32;
+    output.resize(output.len() + padding, 0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_call() {
+        let data = encode_call("baz(uint32,bool)", &[json!(69), json!(true)]).unwrap();
+        assert_eq!(
+            hex::encode(data),
+            "cdcd77c0\
+             0000000000000000000000000000000000000000000000000000000000000045\
+             0000000000000000000000000000000000000000000000000000000000000001"
+        );
+    }
+
+    #[test]
+    fn test_encode_dynamic() {
+        let types = ParamType::parse_list("bytes,bool,uint[]").unwrap();
+        let data = encode(
+            &types,
+            &[json!("0x64617665"), json!(true), json!([1, 2, 3])],
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(data),
+            "0000000000000000000000000000000000000000000000000000000000000060\
+             0000000000000000000000000000000000000000000000000000000000000001\
+             00000000000000000000000000000000000000000000000000000000000000a0\
+             0000000000000000000000000000000000000000000000000000000000000004\
+             6461766500000000000000000000000000000000000000000000000000000000\
+             0000000000000000000000000000000000000000000000000000000000000003\
+             0000000000000000000000000000000000000000000000000000000000000001\
+             0000000000000000000000000000000000000000000000000000000000000002\
+             0000000000000000000000000000000000000000000000000000000000000003"
+        );
+    }
+
+    #[test]
+    fn test_encode_int() {
+        let types = [ParamType::Int(8)];
+        assert_eq!(encode(&types, &[json!(-1)]).unwrap(), vec![0xff; 32]);
+        assert!(encode(&types, &[json!(-128)]).is_ok());
+        assert!(encode(&types, &[json!(-129)]).is_err());
+        assert!(encode(&types, &[json!(128)]).is_err());
+    }
+}
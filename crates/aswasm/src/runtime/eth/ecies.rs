@@ -0,0 +1,144 @@
+//! ECIES as used by Ethereum devp2p: secp256k1 ECDH, NIST SP 800-56 concatenation KDF,
+//! AES-128-CTR and HMAC-SHA-256.
+//!
+//! Encrypted message layout: `R (65 bytes) || iv (16 bytes) || ciphertext || mac (32 bytes)`.
+
+use aes_ctr::cipher::generic_array::GenericArray;
+use aes_ctr::cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use anyhow::{anyhow, bail};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use secp256k1::{PublicKey, SecretKey};
+use sha2::{Digest, Sha256};
+
+const PUBLIC_KEY_SIZE: usize = 65;
+const IV_SIZE: usize = 16;
+const MAC_SIZE: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Derives encryption and MAC keys from the raw ECDH secret.
+fn derive_keys(public: &PublicKey, secret: &SecretKey) -> anyhow::Result<([u8; 16], [u8; 32])> {
+    let mut point = public.clone();
+    point
+        .tweak_mul_assign(secret)
+        .map_err(|e| anyhow!("invalid public key: {}", e))?;
+    let shared_x = &point.serialize()[1..33];
+
+    // single round of concat KDF, with empty shared info
+    let mut kdf = Sha256::new();
+    kdf.input(&1u32.to_be_bytes());
+    kdf.input(shared_x);
+    let key_material = kdf.result();
+
+    let mut enc_key = [0u8; 16];
+    enc_key.copy_from_slice(&key_material[..16]);
+    let mut mac_key = [0u8; 32];
+    mac_key.copy_from_slice(&Sha256::digest(&key_material[16..]));
+    Ok((enc_key, mac_key))
+}
+
+fn mac(mac_key: &[u8], data: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_varkey(mac_key).expect("HMAC accepts keys of any size");
+    mac.input(data);
+    mac
+}
+
+pub fn encrypt(public: &PublicKey, plaintext: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let ephemeral = SecretKey::random(&mut rand::thread_rng());
+    let (enc_key, mac_key) = derive_keys(public, &ephemeral)?;
+
+    let mut output = Vec::with_capacity(PUBLIC_KEY_SIZE + IV_SIZE + plaintext.len() + MAC_SIZE);
+    output.extend_from_slice(&PublicKey::from_secret_key(&ephemeral).serialize());
+
+    let mut iv = [0u8; IV_SIZE];
+    rand::thread_rng().fill(&mut iv);
+    let mut ciphertext = plaintext.to_vec();
+    Aes128Ctr::new(
+        GenericArray::from_slice(&enc_key),
+        GenericArray::from_slice(&iv),
+    )
+    .apply_keystream(&mut ciphertext);
+    output.extend_from_slice(&iv);
+    output.extend_from_slice(&ciphertext);
+
+    let tag = mac(&mac_key, &output[PUBLIC_KEY_SIZE..]).result().code();
+    output.extend_from_slice(&tag);
+    Ok(output)
+}
+
+pub fn decrypt(secret: &SecretKey, blob: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if blob.len() < PUBLIC_KEY_SIZE + IV_SIZE + MAC_SIZE {
+        bail!("encrypted message too short");
+    }
+    let public = PublicKey::parse_slice(&blob[..PUBLIC_KEY_SIZE], None)
+        .map_err(|e| anyhow!("invalid ephemeral key: {}", e))?;
+    let (enc_key, mac_key) = derive_keys(&public, secret)?;
+
+    let (data, tag) = blob[PUBLIC_KEY_SIZE..].split_at(blob.len() - PUBLIC_KEY_SIZE - MAC_SIZE);
+    if mac(&mac_key, data).verify(tag).is_err() {
+        bail!("invalid message authentication code");
+    }
+
+    let (iv, ciphertext) = data.split_at(IV_SIZE);
+    let mut plaintext = ciphertext.to_vec();
+    Aes128Ctr::new(
+        GenericArray::from_slice(&enc_key),
+        GenericArray::from_slice(iv),
+    )
+    .apply_keystream(&mut plaintext);
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let secret = SecretKey::random(&mut rand::thread_rng());
+        let public = PublicKey::from_secret_key(&secret);
+        let mut blob = encrypt(&public, b"ballot: yes").unwrap();
+        assert_eq!(blob.len(), PUBLIC_KEY_SIZE + IV_SIZE + 11 + MAC_SIZE);
+        assert_eq!(decrypt(&secret, &blob).unwrap(), b"ballot: yes");
+
+        blob[PUBLIC_KEY_SIZE + IV_SIZE] ^= 1;
+        assert!(decrypt(&secret, &blob).is_err());
+    }
+
+    /// Legacy (pre EIP-8) auth message from the EIP-8 test vectors, encrypted by geth
+    /// `ecies.Encrypt` to key B.
+    #[test]
+    fn test_decrypt_geth_vector() {
+        let secret = SecretKey::parse_slice(
+            &hex::decode("b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291")
+                .unwrap(),
+        )
+        .unwrap();
+        let mut blob = hex::decode(
+            "048ca79ad18e4b0659fab4853fe5bc58eb83992980f4c9cc147d2aa31532efd29a3d3dc6a3d89eaf\
+             913150cfc777ce0ce4af2758bf4810235f6e6ceccfee1acc6b22c005e9e3a49d6448610a58e98744\
+             ba3ac0399e82692d67c1f58849050b3024e21a52c9d3b01d871ff5f210817912773e610443a9ef14\
+             2e91cdba0bd77b5fdf0769b05671fc35f83d83e4d3b0b000c6b2a1b1bba89e0fc51bf4e460df3105\
+             c444f14be226458940d6061c296350937ffd5e3acaceeaaefd3c6f74be8e23e0f45163cc7ebd7622\
+             0f0128410fd05250273156d548a414444ae2f7dea4dfca2d43c057adb701a715bf59f6fb66b2d1d2\
+             0f2c703f851cbf5ac47396d9ca65b6260bd141ac4d53e2de585a73d1750780db4c9ee4cd4d225173\
+             a4592ee77e2bd94d0be3691f3b406f9bba9b591fc63facc016bfa8",
+        )
+        .unwrap();
+        let plaintext = hex::decode(
+            "299ca6acfd35e3d72d8ba3d1e2b60b5561d5af5218eb5bc182045769eb4226910a301acae3b369ff\
+             fc4a4899d6b02531e89fd4fe36a2cf0d93607ba470b50f78003eb781e508ac1fff27c06cd192e2fe\
+             526f85f8f0e266ea55064ba8aefb868fd9fda1cff674c90c9a197539fe3dfb53086ace64f83ed7c6\
+             eabec741f7f381cc803e52ab2cd55d5569bce4347107a310dfd5f88a010cd2ffd1005ca406f18428\
+             777e968bba13b6c50e2c4cd7f241cc0d64d1ac25c7f5952df231ac6a2bda8ee5d600",
+        )
+        .unwrap();
+        assert_eq!(decrypt(&secret, &blob).unwrap(), plaintext);
+
+        let last = blob.len() - 1;
+        blob[last] ^= 1;
+        assert!(decrypt(&secret, &blob).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! Recursive length prefix encoding.
+
+use super::uint;
+use anyhow::bail;
+use serde_json::Value;
+
+#[derive(Clone)]
+pub enum Item {
+    Bytes(Vec<u8>),
+    List(Vec<Item>),
+}
+
+impl Item {
+    /// Integer encoded as big-endian bytes without leading zeros.
+    pub fn uint(n: &uint::U256) -> Self {
+        Item::Bytes(uint::minimal(n).to_vec())
+    }
+
+    /// Converts JSON document into RLP item.
+    ///
+    /// Arrays map to lists, `0x` prefixed strings to byte strings, and numbers
+    /// or decimal strings to integers.
+    pub fn from_json(value: &Value) -> anyhow::Result<Self> {
+        Ok(match value {
+            Value::Array(items) => Item::List(
+                items
+                    .iter()
+                    .map(Item::from_json)
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            Value::String(s) if s.starts_with("0x") => Item::Bytes(hex::decode(&s[2..])?),
+            Value::String(_) | Value::Number(_) => Item::uint(&uint::from_json(value)?),
+            _ => bail!("unable to encode {} as rlp", value),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.encode_to(&mut output);
+        output
+    }
+
+    fn encode_to(&self, output: &mut Vec<u8>) {
+        match self {
+            Item::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => output.push(bytes[0]),
+            Item::Bytes(bytes) => {
+                encode_length(output, bytes.len(), 0x80);
+                output.extend_from_slice(bytes);
+            }
+            Item::List(items) => {
+                let mut payload = Vec::new();
+                for item in items {
+                    item.encode_to(&mut payload);
+                }
+                encode_length(output, payload.len(), 0xc0);
+                output.extend_from_slice(&payload);
+            }
+        }
+    }
+}
+
+fn encode_length(output: &mut Vec<u8>, len: usize, offset: u8) {
+    if len < 56 {
+        output.push(offset + len as u8);
+    } else {
+        let len_bytes = (len as u64).to_be_bytes();
+        let len_bytes = &len_bytes[((len as u64).leading_zeros() / 8) as usize..];
+        output.push(offset + 55 + len_bytes.len() as u8);
+        output.extend_from_slice(len_bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_json(json: &str) -> String {
+        hex::encode(
+            Item::from_json(&serde_json::from_str(json).unwrap())
+                .unwrap()
+                .encode(),
+        )
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode_json(r#""0x646f67""#), "83646f67");
+        assert_eq!(
+            encode_json(r#"["0x636174", "0x646f67"]"#),
+            "c88363617483646f67"
+        );
+        assert_eq!(encode_json(r#""0x""#), "80");
+        assert_eq!(encode_json("[]"), "c0");
+        assert_eq!(encode_json("0"), "80");
+        assert_eq!(encode_json("15"), "0f");
+        assert_eq!(encode_json(r#""1024""#), "820400");
+        assert_eq!(encode_json(r#"[[], [[]], [[], [[]]]]"#), "c7c0c1c0c3c0c1c0");
+        let long = format!(r#""0x{}""#, "61".repeat(56));
+        assert_eq!(encode_json(&long), format!("b838{}", "61".repeat(56)));
+    }
+}
@@ -0,0 +1,337 @@
+//! Building and signing of Ethereum transactions.
+
+use super::rlp::Item;
+use super::{eth_hash_parts, uint, SecretKey};
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Transaction fields, as passed by guests in JSON.
+///
+/// Integers are JSON numbers, decimal or `0x` prefixed hex strings. Type is
+/// inferred from fee fields when not given: `maxFeePerGas` selects EIP-1559,
+/// `accessList` selects EIP-2930, otherwise legacy EIP-155 transaction is built.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxRequest {
+    #[serde(default, rename = "type")]
+    tx_type: Option<Value>,
+    chain_id: Value,
+    nonce: Value,
+    #[serde(alias = "gasLimit")]
+    gas: Value,
+    #[serde(default)]
+    gas_price: Option<Value>,
+    #[serde(default)]
+    max_priority_fee_per_gas: Option<Value>,
+    #[serde(default)]
+    max_fee_per_gas: Option<Value>,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    value: Option<Value>,
+    #[serde(default)]
+    data: Option<String>,
+    #[serde(default)]
+    access_list: Option<Vec<AccessListItem>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListItem {
+    address: String,
+    storage_keys: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxKind {
+    Legacy,
+    AccessList,
+    DynamicFee,
+}
+
+impl TxRequest {
+    pub fn kind(&self) -> anyhow::Result<TxKind> {
+        let tx_type = match &self.tx_type {
+            Some(value) => uint::to_u64(&uint::from_json(value)?)?,
+            None if self.max_fee_per_gas.is_some() => 2,
+            None if self.access_list.is_some() => 1,
+            None => 0,
+        };
+        Ok(match tx_type {
+            0 => TxKind::Legacy,
+            1 => TxKind::AccessList,
+            2 => TxKind::DynamicFee,
+            _ => bail!("unsupported transaction type: {}", tx_type),
+        })
+    }
+
+    /// Signs transaction, returns raw bytes ready for `eth_sendRawTransaction`.
+    pub fn sign(&self, secret: &SecretKey) -> anyhow::Result<Vec<u8>> {
+        let kind = self.kind()?;
+        let chain_id = uint::from_json(&self.chain_id)?;
+        let mut fields = vec![Item::uint(&chain_id), uint_item(&self.nonce)?];
+        match kind {
+            TxKind::Legacy | TxKind::AccessList => {
+                fields.push(required("gasPrice", &self.gas_price)?)
+            }
+            TxKind::DynamicFee => {
+                fields.push(required(
+                    "maxPriorityFeePerGas",
+                    &self.max_priority_fee_per_gas,
+                )?);
+                fields.push(required("maxFeePerGas", &self.max_fee_per_gas)?);
+            }
+        }
+        fields.push(uint_item(&self.gas)?);
+        fields.push(Item::Bytes(match &self.to {
+            Some(to) => address(to)?,
+            None => Vec::new(),
+        }));
+        fields.push(match &self.value {
+            Some(value) => uint_item(value)?,
+            None => Item::Bytes(Vec::new()),
+        });
+        fields.push(Item::Bytes(match &self.data {
+            Some(data) => hex_bytes(data)?,
+            None => Vec::new(),
+        }));
+
+        if kind == TxKind::Legacy {
+            // EIP-155: chain id takes place of the signature in signed payload
+            let mut payload = fields[1..].to_vec();
+            payload.extend(vec![
+                Item::uint(&chain_id),
+                Item::Bytes(Vec::new()),
+                Item::Bytes(Vec::new()),
+            ]);
+            let hash = eth_hash_parts(&[Item::List(payload).encode()]);
+            let signature = hash.sign_by(secret);
+            let v = u64::from(signature.recovery_id.serialize())
+                + uint::to_u64(&chain_id)?
+                    .checked_mul(2)
+                    .and_then(|v| v.checked_add(35))
+                    .ok_or_else(|| anyhow!("chain id out of range"))?;
+            let mut signed = fields[1..].to_vec();
+            signed.push(Item::uint(&uint::from_u64(v)));
+            signed.extend(signature_rs(&signature.signature.serialize()));
+            return Ok(Item::List(signed).encode());
+        }
+
+        fields.push(self.access_list()?);
+        let prefix = match kind {
+            TxKind::AccessList => 1u8,
+            _ => 2u8,
+        };
+        let hash = eth_hash_parts(&[&[prefix][..], &Item::List(fields.clone()).encode()]);
+        let signature = hash.sign_by(secret);
+        fields.push(Item::uint(&uint::from_u64(
+            signature.recovery_id.serialize().into(),
+        )));
+        fields.extend(signature_rs(&signature.signature.serialize()));
+        let mut output = vec![prefix];
+        output.extend(Item::List(fields).encode());
+        Ok(output)
+    }
+
+    fn access_list(&self) -> anyhow::Result<Item> {
+        let items = self.access_list.as_deref().unwrap_or_default();
+        Ok(Item::List(
+            items
+                .iter()
+                .map(|item| {
+                    let keys = item
+                        .storage_keys
+                        .iter()
+                        .map(|key| {
+                            let key = hex_bytes(key)?;
+                            if key.len() != 32 {
+                                bail!("invalid storage key: 0x{}", hex::encode(key));
+                            }
+                            Ok(Item::Bytes(key))
+                        })
+                        .collect::<anyhow::Result<_>>()?;
+                    Ok(Item::List(vec![
+                        Item::Bytes(address(&item.address)?),
+                        Item::List(keys),
+                    ]))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        ))
+    }
+}
+
+fn uint_item(value: &Value) -> anyhow::Result<Item> {
+    Ok(Item::uint(&uint::from_json(value)?))
+}
+
+fn required(name: &str, value: &Option<Value>) -> anyhow::Result<Item> {
+    match value {
+        Some(value) => uint_item(value),
+        None => bail!("missing {} field", name),
+    }
+}
+
+fn signature_rs(signature: &[u8; 64]) -> Vec<Item> {
+    let mut r = [0u8; 32];
+    let mut s = [0u8; 32];
+    r.copy_from_slice(&signature[..32]);
+    s.copy_from_slice(&signature[32..]);
+    vec![Item::uint(&r), Item::uint(&s)]
+}
+
+fn address(s: &str) -> anyhow::Result<Vec<u8>> {
+    let bytes = hex_bytes(s)?;
+    if bytes.len() != 20 {
+        bail!("invalid address: {}", s);
+    }
+    Ok(bytes)
+}
+
+fn hex_bytes(s: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(hex::decode(s.strip_prefix("0x").unwrap_or(s))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{EthAddress, EthHash, RecoverableSignature, ToEthAddress};
+    use super::*;
+
+    fn secret() -> SecretKey {
+        SecretKey::parse(&[0x46; 32]).unwrap()
+    }
+
+    /// Recovers signer of a published signing hash and `r || s || v` signature.
+    fn recover(hash: &str, signature: &str) -> EthAddress {
+        let hash = EthHash::parse_slice(&hex::decode(hash).unwrap()).unwrap();
+        RecoverableSignature::from_hex(signature)
+            .unwrap()
+            .recover_pub_key(&hash)
+            .unwrap()
+            .to_eth_address()
+    }
+
+    #[test]
+    fn test_sign_eip155() {
+        // example from EIP-155
+        let tx: TxRequest = serde_json::from_str(
+            r#"{
+                "chainId": 1,
+                "nonce": 9,
+                "gasPrice": "20000000000",
+                "gas": 21000,
+                "to": "0x3535353535353535353535353535353535353535",
+                "value": "1000000000000000000"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(tx.kind().unwrap(), TxKind::Legacy);
+        assert_eq!(
+            hex::encode(tx.sign(&secret()).unwrap()),
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+        let sender = recover(
+            "daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53",
+            "28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276\
+             67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83\
+             00",
+        );
+        assert_eq!(sender, secret().to_eth_address());
+        assert_eq!(
+            sender,
+            EthAddress::from_hex("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f").unwrap()
+        );
+    }
+
+    // Typed transaction vectors below are signed by @ethereumjs/tx with key `0x00..01`,
+    // hashes are the signing hashes reported by it.
+
+    fn vector_key() -> SecretKey {
+        let mut key = [0u8; 32];
+        key[31] = 1;
+        SecretKey::parse(&key).unwrap()
+    }
+
+    const VECTOR_ACCESS_LIST: &str = r#"[{
+        "address": "0x0000000000000000000000000000000000000000",
+        "storageKeys": ["0x0000000000000000000000000000000000000000000000000000000000000000"]
+    }, {
+        "address": "0x0000000000000000000000000000000000000000",
+        "storageKeys": ["0x0000000000000000000000000000000000000000000000000000000000000000"]
+    }]"#;
+
+    #[test]
+    fn test_sign_eip2930() {
+        let tx: TxRequest = serde_json::from_str(&format!(
+            r#"{{
+                "chainId": 1,
+                "nonce": 0,
+                "gasPrice": 0,
+                "gas": 0,
+                "to": null,
+                "value": 0,
+                "data": "0x00",
+                "accessList": {}
+            }}"#,
+            VECTOR_ACCESS_LIST
+        ))
+        .unwrap();
+        assert_eq!(tx.kind().unwrap(), TxKind::AccessList);
+        assert_eq!(
+            hex::encode(tx.sign(&vector_key()).unwrap()),
+            "01f8bc01808080808000f870f7940000000000000000000000000000000000000000e1a0000000000000\
+             0000000000000000000000000000000000000000000000000000f7940000000000000000000000000000\
+             000000000000e1a0000000000000000000000000000000000000000000000000000000000000000001a0\
+             caec7134a4ee1b1566fe48334d4cbc194af12af22ba813aa24df588362a41298a05cf4b47bde2e76d6c9\
+             ea0f4affff89a477f8d59b323fe6bd268ca62fa9a3057a"
+        );
+        assert_eq!(
+            recover(
+                "7c65b5138b4a4bb29335bbd25c4ae4a637f4aa1bf02d8a6b4c01d430a89518e1",
+                "caec7134a4ee1b1566fe48334d4cbc194af12af22ba813aa24df588362a41298\
+                 5cf4b47bde2e76d6c9ea0f4affff89a477f8d59b323fe6bd268ca62fa9a3057a\
+                 01",
+            ),
+            EthAddress::from_hex("7e5f4552091a69125d5dfcb7b8c2659029395bdf").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sign_eip1559() {
+        let tx: TxRequest = serde_json::from_str(&format!(
+            r#"{{
+                "chainId": "0x1",
+                "nonce": 0,
+                "maxPriorityFeePerGas": 0,
+                "maxFeePerGas": "0",
+                "gasLimit": 0,
+                "to": null,
+                "value": 0,
+                "data": "0x00",
+                "accessList": {}
+            }}"#,
+            VECTOR_ACCESS_LIST
+        ))
+        .unwrap();
+        assert_eq!(tx.kind().unwrap(), TxKind::DynamicFee);
+        assert_eq!(
+            hex::encode(tx.sign(&vector_key()).unwrap()),
+            "02f8bd0180808080808000f870f7940000000000000000000000000000000000000000e1a00000000000\
+             000000000000000000000000000000000000000000000000000000f79400000000000000000000000000\
+             00000000000000e1a0000000000000000000000000000000000000000000000000000000000000000080\
+             a07a53fb20b46d9cc2600d8dc3168a698d41c0dec029d46db4ba88ffe359bbe409a02536bd58c593edcd\
+             a36c5f2e35ed4db158b0cab202b6b2648403117e483a9b30"
+        );
+        assert_eq!(
+            recover(
+                "c32ac5ba02818ce7bd5d626b7f97b9ae672b42433e4823e119d79c6c8f58c777",
+                "7a53fb20b46d9cc2600d8dc3168a698d41c0dec029d46db4ba88ffe359bbe409\
+                 2536bd58c593edcda36c5f2e35ed4db158b0cab202b6b2648403117e483a9b30\
+                 00",
+            ),
+            vector_key().to_eth_address()
+        );
+    }
+}
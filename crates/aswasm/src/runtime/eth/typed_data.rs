@@ -0,0 +1,195 @@
+//! EIP-712 typed structured data hashing.
+
+use super::abi::ParamType;
+use super::{eth_hash_parts, EthHash};
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    types: BTreeMap<String, Vec<Field>>,
+    primary_type: String,
+    domain: Map<String, Value>,
+    message: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    field_type: String,
+}
+
+impl TypedData {
+    /// Digest to be signed: `keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))`.
+    pub fn hash(&self) -> anyhow::Result<EthHash> {
+        let domain = self.domain_separator()?;
+        let message = self.hash_struct(&self.primary_type, &self.message)?;
+        Ok(eth_hash_parts(&[
+            &b"\x19\x01"[..],
+            domain.as_ref(),
+            message.as_ref(),
+        ]))
+    }
+
+    pub fn domain_separator(&self) -> anyhow::Result<EthHash> {
+        self.hash_struct(DOMAIN_TYPE, &self.domain)
+    }
+
+    fn fields(&self, type_name: &str) -> anyhow::Result<&[Field]> {
+        self.types
+            .get(type_name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| anyhow!("undefined type: {}", type_name))
+    }
+
+    /// Type name with array suffixes removed.
+    fn base_type(type_name: &str) -> &str {
+        type_name.split('[').next().unwrap_or(type_name)
+    }
+
+    fn encode_type(&self, type_name: &str) -> anyhow::Result<String> {
+        let mut deps = BTreeSet::new();
+        self.collect_deps(type_name, &mut deps)?;
+        deps.remove(type_name);
+        let mut output = String::new();
+        for name in std::iter::once(type_name).chain(deps.iter().map(String::as_str)) {
+            let fields = self.fields(name)?;
+            output.push_str(name);
+            output.push('(');
+            for (idx, field) in fields.iter().enumerate() {
+                if idx > 0 {
+                    output.push(',');
+                }
+                output.push_str(&field.field_type);
+                output.push(' ');
+                output.push_str(&field.name);
+            }
+            output.push(')');
+        }
+        Ok(output)
+    }
+
+    fn collect_deps(&self, type_name: &str, deps: &mut BTreeSet<String>) -> anyhow::Result<()> {
+        if !deps.insert(type_name.to_string()) {
+            return Ok(());
+        }
+        for field in self.fields(type_name)? {
+            let base = Self::base_type(&field.field_type);
+            if self.types.contains_key(base) {
+                self.collect_deps(base, deps)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn hash_struct(&self, type_name: &str, data: &Map<String, Value>) -> anyhow::Result<EthHash> {
+        let mut encoded = eth_hash_parts(&[self.encode_type(type_name)?]).0.to_vec();
+        for field in self.fields(type_name)? {
+            let value = data
+                .get(&field.name)
+                .ok_or_else(|| anyhow!("missing {}.{}", type_name, field.name))?;
+            encoded.extend_from_slice(&self.encode_value(&field.field_type, value)?);
+        }
+        Ok(eth_hash_parts(&[encoded]))
+    }
+
+    fn encode_value(&self, type_name: &str, value: &Value) -> anyhow::Result<[u8; 32]> {
+        if let Some(element) = type_name.strip_suffix(']') {
+            let element = &element[..element
+                .rfind('[')
+                .ok_or_else(|| anyhow!("invalid type: {}", type_name))?];
+            let items = value
+                .as_array()
+                .ok_or_else(|| anyhow!("expected array of {}, got {}", element, value))?;
+            let mut encoded = Vec::with_capacity(items.len() * 32);
+            for item in items {
+                encoded.extend_from_slice(&self.encode_value(element, item)?);
+            }
+            return Ok(eth_hash_parts(&[encoded]).0);
+        }
+        if self.types.contains_key(type_name) {
+            let data = value
+                .as_object()
+                .ok_or_else(|| anyhow!("expected {} object, got {}", type_name, value))?;
+            return Ok(self.hash_struct(type_name, data)?.0);
+        }
+        match ParamType::parse(type_name)? {
+            ParamType::String => match value {
+                Value::String(s) => Ok(eth_hash_parts(&[s.as_bytes()]).0),
+                _ => bail!("expected string, got {}", value),
+            },
+            ParamType::Bytes => match value.as_str() {
+                Some(s) => Ok(eth_hash_parts(&[hex::decode(s.strip_prefix("0x").unwrap_or(s))?]).0),
+                None => bail!("expected hex string, got {}", value),
+            },
+            param_type => {
+                let mut encoded = Vec::with_capacity(32);
+                param_type.encode(value, &mut encoded)?;
+                let mut output = [0u8; 32];
+                output.copy_from_slice(&encoded);
+                Ok(output)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // example from EIP-712
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    #[test]
+    fn test_mail() {
+        let typed_data: TypedData = serde_json::from_str(MAIL).unwrap();
+        assert_eq!(
+            typed_data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            format!("{:x}", typed_data.domain_separator().unwrap()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            format!("{:x}", typed_data.hash().unwrap()),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+    }
+}
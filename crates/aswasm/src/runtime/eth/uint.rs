@@ -0,0 +1,108 @@
+//! 256-bit unsigned integers in big-endian byte form.
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+
+pub type U256 = [u8; 32];
+
+/// Parses JSON number, decimal string or `0x` prefixed hex string.
+pub fn from_json(value: &Value) -> anyhow::Result<U256> {
+    match value {
+        Value::Number(n) => {
+            let n = n
+                .as_u64()
+                .ok_or_else(|| anyhow!("expected unsigned integer, got {}", n))?;
+            Ok(from_u64(n))
+        }
+        Value::String(s) => parse(s),
+        _ => bail!("expected integer, got {}", value),
+    }
+}
+
+pub fn parse(s: &str) -> anyhow::Result<U256> {
+    if let Some(digits) = s.strip_prefix("0x") {
+        let digits = digits.trim_start_matches('0');
+        if digits.len() > 64 {
+            bail!("integer overflow: {}", s);
+        }
+        let padded = format!("{:0>64}", digits);
+        let mut output = [0u8; 32];
+        hex::decode_to_slice(&padded, &mut output)
+            .with_context(|| format!("invalid hex: {}", s))?;
+        return Ok(output);
+    }
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("invalid integer: {:?}", s);
+    }
+    let mut output = [0u8; 32];
+    for digit in s.bytes().map(|b| b - b'0') {
+        // output = output * 10 + digit
+        let mut carry = digit as u32;
+        for byte in output.iter_mut().rev() {
+            let v = *byte as u32 * 10 + carry;
+            *byte = v as u8;
+            carry = v >> 8;
+        }
+        if carry != 0 {
+            bail!("integer overflow: {}", s);
+        }
+    }
+    Ok(output)
+}
+
+pub fn from_u64(n: u64) -> U256 {
+    let mut output = [0u8; 32];
+    output[24..].copy_from_slice(&n.to_be_bytes());
+    output
+}
+
+pub fn to_u64(n: &U256) -> anyhow::Result<u64> {
+    if n[..24].iter().any(|b| *b != 0) {
+        bail!("integer does not fit in 64 bits");
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&n[24..]);
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Number of significant bits.
+pub fn bits(n: &U256) -> usize {
+    match n.iter().position(|b| *b != 0) {
+        Some(idx) => (32 - idx) * 8 - n[idx].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+/// Big-endian bytes without leading zeros.
+pub fn minimal(n: &U256) -> &[u8] {
+    let start = n.iter().position(|b| *b != 0).unwrap_or(32);
+    &n[start..]
+}
+
+/// Two's complement negation.
+pub fn negate(n: &U256) -> U256 {
+    let mut output = [0u8; 32];
+    let mut carry = 1u16;
+    for (out, byte) in output.iter_mut().zip(n.iter()).rev() {
+        let v = (!*byte) as u16 + carry;
+        *out = v as u8;
+        carry = v >> 8;
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1024").unwrap(), from_u64(1024));
+        assert_eq!(parse("0x400").unwrap(), from_u64(1024));
+        assert_eq!(minimal(&parse("0").unwrap()), &[] as &[u8]);
+        assert_eq!(bits(&from_u64(255)), 8);
+        assert_eq!(negate(&from_u64(1)), [0xff; 32]);
+        assert!(parse(&"9".repeat(78)).is_err());
+        assert!(parse("12a").is_err());
+    }
+}
@@ -1,45 +1,269 @@
-use super::AsMem;
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use super::{AbiVersion, AsMem};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
+use super::keystore::KEYSTORE_DIR;
+use super::Allocator;
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::ops::Not;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::UNIX_EPOCH;
 use wasmtime::{Caller, Linker, Trap};
-use ya_runtime_api::deploy::ContainerVolume;
+use ya_runtime_common::dir_size;
 
-const MAX_FDS: usize = 4096;
+/// Default limit of open file descriptors.
+pub const DEFAULT_MAX_FDS: usize = 4096;
 const MIN_FD: i32 = 256;
 
-pub struct Fd(std::fs::File);
+const KIND_FILE: u32 = 1;
+const KIND_DIR: u32 = 2;
+/// Size of `io.stat` result: kind (u32), reserved (u32), size (u64), mtime in ms (u64).
+const STAT_SIZE: usize = 24;
+
+/// Error codes returned by `io` functions in place of a result.
+///
+/// Values are part of the guest ABI and must not change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoError {
+    /// Unclassified host error, see `io.lastError()`.
+    Other = -1,
+    /// Operation is not allowed by access mode of the volume.
+    AccessDenied = -2,
+    NotFound = -3,
+    /// Host file system refused the operation.
+    PermissionDenied = -4,
+    AlreadyExists = -5,
+    /// Malformed path or path leaving its volume.
+    InvalidPath = -6,
+    /// Path is outside of all volumes.
+    NoVolume = -7,
+    BadFd = -8,
+    TooManyFds = -9,
+    InvalidArgument = -10,
+    /// Write would exceed configured size limit.
+    LimitExceeded = -11,
+}
+
+impl IoError {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    fn error(self, message: &'static str) -> std::io::Error {
+        let kind = match self {
+            Self::AccessDenied | Self::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            Self::NotFound | Self::NoVolume => std::io::ErrorKind::NotFound,
+            Self::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            Self::InvalidPath | Self::BadFd | Self::InvalidArgument => {
+                std::io::ErrorKind::InvalidInput
+            }
+            Self::TooManyFds | Self::LimitExceeded | Self::Other => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, CodedError(self, message))
+    }
+
+    /// Code of a host error, errors raised by `io` functions keep their own code.
+    pub fn classify(e: &std::io::Error) -> Self {
+        if let Some(CodedError(code, _)) = e.get_ref().and_then(|inner| inner.downcast_ref()) {
+            return *code;
+        }
+        match e.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            std::io::ErrorKind::AlreadyExists => Self::AlreadyExists,
+            std::io::ErrorKind::InvalidInput => Self::InvalidArgument,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CodedError(IoError, &'static str);
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.1)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// Guest access to a volume, as declared by manifest mount point.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessMode {
+    Ro,
+    Rw,
+    Wo,
+    Private,
+}
+
+impl Default for AccessMode {
+    fn default() -> Self {
+        Self::Rw
+    }
+}
+
+impl AccessMode {
+    fn allows(self, access: Access) -> bool {
+        !matches!(
+            (self, access),
+            (Self::Ro, Access::Write) | (Self::Wo, Access::Read)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// Volume directory mounted in the guest file system.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Volume {
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub mode: AccessMode,
+    /// Maximum size of all files in the volume, from the manifest.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+}
+
+/// Disk usage of a volume, reported after run.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VolumeUsage {
+    pub path: String,
+    pub used: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+}
+
+/// Bytes stored in a volume, checked against its quota on write.
+struct UsageCounter {
+    used: Cell<u64>,
+    quota: Option<u64>,
+}
+
+impl UsageCounter {
+    /// Accounts file resize, fails when growing over the quota.
+    fn resize(&self, from: u64, to: u64) -> std::io::Result<()> {
+        let used = (self.used.get() + to).saturating_sub(from);
+        if to > from && self.quota.map_or(false, |quota| used > quota) {
+            return Err(IoError::LimitExceeded.error("volume quota exceeded"));
+        }
+        self.used.set(used);
+        Ok(())
+    }
+}
+
+/// Limits of guest file system access.
+#[derive(Clone, Debug)]
+pub struct IoLimits {
+    pub max_fds: usize,
+    /// Maximum size of a file written by the guest.
+    pub max_file_size: Option<u64>,
+    /// Caps volume quotas, applies also to volumes without one.
+    pub max_volume_size: Option<u64>,
+}
+
+impl Default for IoLimits {
+    fn default() -> Self {
+        IoLimits {
+            max_fds: DEFAULT_MAX_FDS,
+            max_file_size: None,
+            max_volume_size: None,
+        }
+    }
+}
+
+pub struct Fd {
+    file: std::fs::File,
+    append: bool,
+    max_size: Option<u64>,
+    usage: Option<Rc<UsageCounter>>,
+}
 
 impl Fd {
+    fn new(file: std::fs::File) -> Self {
+        Fd {
+            file,
+            append: false,
+            max_size: None,
+            usage: None,
+        }
+    }
+
     fn read(&mut self, slice: &mut [u8]) -> std::io::Result<i32> {
         assert!(slice.len() < 1 << 32);
-        let s = self.0.read(slice)?;
+        let s = self.file.read(slice)?;
         Ok(s as i32)
     }
 
     fn write(&mut self, slice: &[u8]) -> std::io::Result<i32> {
         assert!(slice.len() < 1 << 32);
-        self.0.write_all(slice)?;
+        if self.max_size.is_some() || self.usage.is_some() {
+            let size = self.size()?;
+            let offset = if self.append {
+                size
+            } else {
+                self.file.seek(SeekFrom::Current(0))?
+            };
+            let new_size = size.max(offset + slice.len() as u64);
+            if self.max_size.map_or(false, |max_size| new_size > max_size) {
+                return Err(IoError::LimitExceeded.error("file size limit exceeded"));
+            }
+            if let Some(usage) = &self.usage {
+                usage.resize(size, new_size)?;
+            }
+        }
+        self.file.write_all(slice)?;
         Ok(slice.len() as i32)
     }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn seek(&mut self, offset: i64, whence: i32) -> std::io::Result<u64> {
+        let pos = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(IoError::InvalidArgument.error("invalid seek")),
+        };
+        self.file.seek(pos)
+    }
+
+    fn read_all(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.file.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
 }
 
 pub struct FdStateInner {
-    vols: Vec<ContainerVolume>,
+    vols: Vec<Volume>,
     base_dir: PathBuf,
+    limits: IoLimits,
+    /// Usage of volumes written to, by volume name.
+    usage: HashMap<String, Rc<UsageCounter>>,
     fds: BTreeMap<i32, Fd>,
     n_fds: i32,
+    last_error: Option<String>,
 }
 
 impl FdStateInner {
-    fn add_fd(&mut self, fd: Fd) -> i32 {
-        if self.fds.len() > 4096 {
-            return -1;
+    fn add_fd(&mut self, fd: Fd) -> std::io::Result<i32> {
+        if self.fds.len() >= self.limits.max_fds {
+            return Err(IoError::TooManyFds.error("too many open files"));
         }
         loop {
             let ifd = self.n_fds;
@@ -50,42 +274,142 @@ impl FdStateInner {
 
             if self.fds.contains_key(&ifd).not() {
                 let _ = self.fds.insert(ifd, fd);
-                return ifd;
+                return Ok(ifd);
             }
         }
     }
 
+    /// Usage counter of the volume, initialized with the size of its contents.
+    fn usage(&mut self, vol: &Volume) -> std::io::Result<Rc<UsageCounter>> {
+        if let Some(usage) = self.usage.get(&vol.name) {
+            return Ok(usage.clone());
+        }
+        let quota = match (vol.quota, self.limits.max_volume_size) {
+            (Some(quota), Some(max)) => Some(quota.min(max)),
+            (quota, max) => quota.or(max),
+        };
+        let usage = Rc::new(UsageCounter {
+            used: Cell::new(dir_size(&self.base_dir.join(&vol.name))?),
+            quota,
+        });
+        self.usage.insert(vol.name.clone(), usage.clone());
+        Ok(usage)
+    }
+
     fn remove_fd(&mut self, fd: i32) -> bool {
         self.fds.remove(&fd).is_some()
     }
 
-    fn exec<Operation: FnOnce(&mut Fd) -> std::io::Result<i32>>(
-        &mut self,
-        fd: i32,
-        operation: Operation,
-    ) -> std::io::Result<i32> {
-        if let Some(fd) = self.fds.get_mut(&fd) {
-            operation(fd)
-        } else {
-            Ok(-1)
-        }
+    /// Resolves guest path to a host path inside one of the volumes.
+    ///
+    /// Returns `None` for paths outside of volumes. The most specific volume
+    /// wins when mount points are nested.
+    fn find_path(&self, path: &str) -> std::io::Result<Option<PathBuf>> {
+        Ok(self.find_volume_path(path)?.map(|(_, path)| path))
     }
 
-    fn find_path(&self, path: &str) -> std::io::Result<Option<PathBuf>> {
-        if path.chars().any(|ch| ch == '.' || ch == ':') {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "invalid path char",
-            ));
+    /// Resolves path like [`Self::find_path`], and checks volume access mode.
+    fn find_path_for(&self, path: &str, access: Access) -> std::io::Result<Option<PathBuf>> {
+        Ok(self
+            .find_volume_path_for(path, access)?
+            .map(|(_, path)| path))
+    }
+
+    fn find_volume_path_for(
+        &self,
+        path: &str,
+        access: Access,
+    ) -> std::io::Result<Option<(&Volume, PathBuf)>> {
+        match self.find_volume_path(path)? {
+            Some((vol, _)) if !vol.mode.allows(access) => {
+                Err(IoError::AccessDenied.error(match access {
+                    Access::Read => "volume is not readable",
+                    Access::Write => "volume is not writable",
+                }))
+            }
+            resolved => Ok(resolved),
         }
+    }
+
+    fn find_volume_path(&self, path: &str) -> std::io::Result<Option<(&Volume, PathBuf)>> {
+        let components = path_components(path)?;
+        let mut best: Option<(&Volume, usize)> = None;
         for vol in &self.vols {
-            assert!(vol.path.ends_with('/'));
-            if path.starts_with(&vol.path) {
-                let tail = &path[vol.path.len()..];
-                return Ok(Some(self.base_dir.join(&vol.name).join(tail)));
+            let vol_components = path_components(&vol.path)?;
+            let vol_len = vol_components.len();
+            if components.starts_with(&vol_components)
+                && best.iter().all(|(_, best_len)| vol_len > *best_len)
+            {
+                best = Some((vol, vol_len));
             }
         }
-        Ok(None)
+        let (vol, prefix_len) = match best {
+            Some(best) => best,
+            None => return Ok(None),
+        };
+        if vol.mode == AccessMode::Private && components.get(prefix_len) == Some(&KEYSTORE_DIR) {
+            return Err(IoError::AccessDenied.error("keystore is not accessible"));
+        }
+        let root = self.base_dir.join(&vol.name);
+        let resolved = components[prefix_len..]
+            .iter()
+            .fold(root.clone(), |path, component| path.join(component));
+        check_inside(&root, &resolved)?;
+        Ok(Some((vol, resolved)))
+    }
+
+    fn is_volume_root(&self, path: &Path) -> bool {
+        self.vols
+            .iter()
+            .any(|vol| self.base_dir.join(&vol.name) == path)
+    }
+
+    fn is_keystore(&self, path: &Path) -> bool {
+        self.vols.iter().any(|vol| {
+            vol.mode == AccessMode::Private
+                && self.base_dir.join(&vol.name).join(KEYSTORE_DIR) == path
+        })
+    }
+}
+
+fn invalid_path(message: &'static str) -> std::io::Error {
+    IoError::InvalidPath.error(message)
+}
+
+/// Splits absolute guest path into components, skipping empty and `.` ones.
+fn path_components(path: &str) -> std::io::Result<Vec<&str>> {
+    if !path.starts_with('/') {
+        return Err(invalid_path("path must be absolute"));
+    }
+    if path.chars().any(|ch| ch == '\\' || ch == ':' || ch == '\0') {
+        return Err(invalid_path("invalid path char"));
+    }
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(|component| match component {
+            ".." => Err(invalid_path("parent directory references are not allowed")),
+            _ => Ok(component),
+        })
+        .collect()
+}
+
+/// Refuses paths that leave volume directory through symbolic links.
+///
+/// Checks the deepest existing ancestor, a dangling link is refused as its
+/// target can not be verified.
+fn check_inside(root: &Path, path: &Path) -> std::io::Result<()> {
+    let root = root.canonicalize()?;
+    let mut existing = path;
+    while std::fs::symlink_metadata(existing).is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+    }
+    if existing.canonicalize()?.starts_with(&root) {
+        Ok(())
+    } else {
+        Err(invalid_path("path leaves volume"))
     }
 }
 
@@ -97,87 +421,256 @@ pub struct FdState {
 /*
 export declare namespace io {
   type Fd=i32;
+  // opens file for writing, truncates existing file.
   export function wopen(path: string) : Fd;
+  // opens file for writing at its end.
+  export function aopen(path: string) : Fd;
   export function ropen(path: string) : Fd;
-  export function write(fd: Fd, bytes : ArrayBuffer) : i32;
-  export function read(fd: Fd, bytes : ArrayBuffer) : i32;
+  export function write(fd: Fd, bytes : ArrayBuffer | Uint8Array) : i32;
+  export function read(fd: Fd, bytes : ArrayBuffer | Uint8Array) : i32;
+  // reads rest of the file.
+  export function readAll(fd: Fd) : ArrayBuffer | null;
+  export function size(fd: Fd) : i64;
+  // whence: 0 - from start, 1 - from current position, 2 - from end. returns new position.
+  export function seek(fd: Fd, offset: i64, whence: i32) : i64;
   export function close(fd: Fd) : void;
+  // 24 bytes: kind u32 (1 - file, 2 - directory), reserved u32, size u64, mtime u64 (ms).
+  export function stat(path: string) : ArrayBuffer | null;
+  // newline separated entry names, directories end with '/'.
+  export function list(dir: string) : string | null;
+  export function remove(path: string) : i32;
+  export function mkdir(path: string) : i32;
+  // description of the last failure, functions return negative error codes
+  // or null when they fail.
+  export function lastError() : string | null;
 }
 */
 
 impl FdState {
-    fn new(base_dir: PathBuf, vols: Vec<ContainerVolume>) -> Self {
+    fn new(base_dir: PathBuf, vols: Vec<Volume>, limits: IoLimits) -> Self {
         let inner = Rc::new(RefCell::new(FdStateInner {
             vols,
             base_dir,
+            limits,
+            usage: Default::default(),
             fds: Default::default(),
             n_fds: MIN_FD,
+            last_error: None,
         }));
 
         FdState { inner }
     }
 
-    fn open_write(&self, path: &str) -> std::io::Result<i32> {
+    /// Disk usage of all volumes.
+    pub fn volume_usage(&self) -> std::io::Result<Vec<VolumeUsage>> {
         let mut b = (*self.inner).borrow_mut();
-        if let Some(resolved_path) = b.find_path(path)? {
-            let f = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(resolved_path)?;
-            Ok(b.add_fd(Fd(f)))
-        } else {
-            Ok(-1)
-        }
+        let vols = b.vols.clone();
+        vols.iter()
+            .map(|vol| {
+                let usage = b.usage(vol)?;
+                Ok(VolumeUsage {
+                    path: vol.path.clone(),
+                    used: usage.used.get(),
+                    quota: usage.quota,
+                })
+            })
+            .collect()
+    }
+
+    fn open_write(&self, path: &str, append: bool) -> std::io::Result<i32> {
+        let mut b = (*self.inner).borrow_mut();
+        let (vol, resolved_path) = match b.find_volume_path_for(path, Access::Write)? {
+            Some((vol, resolved_path)) => (vol.clone(), resolved_path),
+            None => return Err(IoError::NoVolume.error("path outside of volumes")),
+        };
+        let usage = b.usage(&vol)?;
+        let prev_size = match std::fs::metadata(&resolved_path) {
+            Ok(meta) if !append => meta.len(),
+            _ => 0,
+        };
+        let f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(resolved_path)?;
+        usage.resize(prev_size, 0)?;
+        let max_size = b.limits.max_file_size;
+        b.add_fd(Fd {
+            file: f,
+            append,
+            max_size,
+            usage: Some(usage),
+        })
     }
 
     fn open_read(&self, path: &str) -> std::io::Result<i32> {
         let mut b = (*self.inner).borrow_mut();
-        if let Some(resolved_path) = b.find_path(path)? {
+        if let Some(resolved_path) = b.find_path_for(path, Access::Read)? {
             let f = OpenOptions::new().read(true).open(resolved_path)?;
-            Ok(b.add_fd(Fd(f)))
+            b.add_fd(Fd::new(f))
         } else {
-            Ok(-1)
+            Err(IoError::NoVolume.error("path outside of volumes"))
         }
     }
 
     fn write(&self, fd: i32, buf: &[u8]) -> std::io::Result<i32> {
-        (*self.inner).borrow_mut().exec(fd, |f| f.write(buf))
+        self.with_fd(fd, |f| f.write(buf))
     }
 
     fn read(&self, fd: i32, buf: &mut [u8]) -> std::io::Result<i32> {
-        (*self.inner).borrow_mut().exec(fd, |f| f.read(buf))
+        self.with_fd(fd, |f| f.read(buf))
     }
 
-    fn close(&self, fd: i32) {
+    fn close(&self, fd: i32) -> std::io::Result<()> {
         let mut b = (*self.inner).borrow_mut();
-        let _ = b.remove_fd(fd);
+        if b.remove_fd(fd) {
+            Ok(())
+        } else {
+            Err(IoError::BadFd.error("bad file descriptor"))
+        }
+    }
+
+    /// Records failed operation for `io.lastError()`.
+    fn check<T>(&self, op: &str, result: std::io::Result<T>) -> Result<T, IoError> {
+        result.map_err(|e| {
+            let code = IoError::classify(&e);
+            log::debug!("io.{} failed ({:?}): {}", op, code, e);
+            (*self.inner).borrow_mut().last_error = Some(format!("{}: {}", op, e));
+            code
+        })
+    }
+
+    /// Result of an operation returning `i32` to the guest.
+    fn status(&self, op: &str, result: std::io::Result<i32>) -> i32 {
+        self.check(op, result).unwrap_or_else(IoError::code)
+    }
+
+    fn last_error(&self) -> Option<String> {
+        (*self.inner).borrow().last_error.clone()
+    }
+
+    fn with_fd<T, F: FnOnce(&mut Fd) -> std::io::Result<T>>(
+        &self,
+        fd: i32,
+        operation: F,
+    ) -> std::io::Result<T> {
+        match (*self.inner).borrow_mut().fds.get_mut(&fd) {
+            Some(fd) => operation(fd),
+            None => Err(IoError::BadFd.error("bad file descriptor")),
+        }
+    }
+
+    fn size(&self, fd: i32) -> std::io::Result<i64> {
+        self.with_fd(fd, |f| f.size()).map(|size| size as i64)
+    }
+
+    fn seek(&self, fd: i32, offset: i64, whence: i32) -> std::io::Result<i64> {
+        self.with_fd(fd, |f| f.seek(offset, whence))
+            .map(|pos| pos as i64)
+    }
+
+    fn read_all(&self, fd: i32) -> std::io::Result<Vec<u8>> {
+        self.with_fd(fd, |f| f.read_all())
+    }
+
+    fn resolve(&self, path: &str, access: Access) -> std::io::Result<PathBuf> {
+        (*self.inner)
+            .borrow()
+            .find_path_for(path, access)?
+            .ok_or_else(|| IoError::NoVolume.error("path outside of volumes"))
+    }
+
+    fn stat(&self, path: &str) -> std::io::Result<[u8; STAT_SIZE]> {
+        let meta = std::fs::metadata(self.resolve(path, Access::Read)?)?;
+        let kind = if meta.is_dir() { KIND_DIR } else { KIND_FILE };
+        let mtime = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut output = [0u8; STAT_SIZE];
+        output[0..4].copy_from_slice(&kind.to_le_bytes());
+        output[8..16].copy_from_slice(&meta.len().to_le_bytes());
+        output[16..24].copy_from_slice(&mtime.to_le_bytes());
+        Ok(output)
+    }
+
+    fn list(&self, path: &str) -> std::io::Result<String> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.resolve(path, Access::Read)?)? {
+            let entry = entry?;
+            if self.inner.borrow().is_keystore(&entry.path()) {
+                continue;
+            }
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names.sort();
+        Ok(names.join("\n"))
+    }
+
+    fn remove(&self, path: &str) -> std::io::Result<i32> {
+        let mut b = (*self.inner).borrow_mut();
+        let (vol, resolved_path) = match b.find_volume_path_for(path, Access::Write)? {
+            Some((vol, resolved_path)) => (vol.clone(), resolved_path),
+            None => return Err(IoError::NoVolume.error("path outside of volumes")),
+        };
+        if b.is_volume_root(&resolved_path) {
+            return Err(IoError::AccessDenied.error("volume can not be removed"));
+        }
+        let meta = std::fs::metadata(&resolved_path)?;
+        if meta.is_dir() {
+            std::fs::remove_dir(resolved_path)?;
+        } else {
+            let usage = b.usage(&vol)?;
+            std::fs::remove_file(resolved_path)?;
+            usage.resize(meta.len(), 0)?;
+        }
+        Ok(0)
+    }
+
+    fn mkdir(&self, path: &str) -> std::io::Result<i32> {
+        std::fs::create_dir_all(self.resolve(path, Access::Write)?)?;
+        Ok(0)
     }
 }
 
 pub fn link_io(
     module: &str,
     linker: &mut Linker,
+    abi: Option<AbiVersion>,
     base_dir: PathBuf,
-    vols: Vec<ContainerVolume>,
-) -> anyhow::Result<()> {
-    let state = FdState::new(base_dir, vols);
+    vols: Vec<Volume>,
+    limits: IoLimits,
+) -> anyhow::Result<FdState> {
+    let state = FdState::new(base_dir, vols, limits);
 
-    fn decode_result(r: std::io::Result<i32>) -> Result<i32, Trap> {
-        match r {
-            Ok(v) => Ok(v),
-            Err(_) => Ok(-1),
-        }
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.wopen",
+            move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
+                let mem = AsMem::for_caller(&caller, abi)?;
+                let path = mem.decode_str(path_ptr)?;
+                Ok(state.status("wopen", state.open_write(&path, false)))
+            },
+        )?;
     }
 
     {
         let state = state.clone();
         linker.func(
             module,
-            "io.wopen",
+            "io.aopen",
             move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
-                let mem = AsMem::for_caller(&caller)?;
+                let mem = AsMem::for_caller(&caller, abi)?;
                 let path = mem.decode_str(path_ptr)?;
-                decode_result(state.open_write(&path))
+                Ok(state.status("aopen", state.open_write(&path, true)))
             },
         )?;
     }
@@ -188,9 +681,9 @@ pub fn link_io(
             module,
             "io.ropen",
             move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
-                let mem = AsMem::for_caller(&caller)?;
+                let mem = AsMem::for_caller(&caller, abi)?;
                 let path = mem.decode_str(path_ptr)?;
-                decode_result(state.open_read(&path))
+                Ok(state.status("ropen", state.open_read(&path)))
             },
         )?;
     }
@@ -201,8 +694,9 @@ pub fn link_io(
             module,
             "io.read",
             move |caller: Caller, fd: i32, buffer: i32| -> Result<i32, Trap> {
-                let mut mem = AsMem::for_caller(&caller)?;
-                unsafe { decode_result(state.read(fd, mem.get_mut_ptr(buffer)?)) }
+                let mut mem = AsMem::for_caller(&caller, abi)?;
+                let result = unsafe { state.read(fd, mem.get_mut_data(buffer)?) };
+                Ok(state.status("read", result))
             },
         )?;
     }
@@ -213,22 +707,433 @@ pub fn link_io(
             module,
             "io.write",
             move |caller: Caller, fd: i32, buffer: i32| -> Result<i32, Trap> {
-                let mem = AsMem::for_caller(&caller)?;
-                unsafe { decode_result(state.write(fd, mem.get_ptr(buffer)?)) }
+                let mem = AsMem::for_caller(&caller, abi)?;
+                let result = unsafe { state.write(fd, mem.get_data(buffer)?) };
+                Ok(state.status("write", result))
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.readAll",
+            move |caller: Caller, fd: i32| -> Result<i32, Trap> {
+                let data = match state.check("readAll", state.read_all(fd)) {
+                    Ok(data) => data,
+                    Err(_) => return Ok(0),
+                };
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_bytes(&data)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.size",
+            move |_caller: Caller, fd: i32| -> Result<i64, Trap> {
+                Ok(state
+                    .check("size", state.size(fd))
+                    .unwrap_or_else(|e| e.code().into()))
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.seek",
+            move |_caller: Caller, fd: i32, offset: i64, whence: i32| -> Result<i64, Trap> {
+                Ok(state
+                    .check("seek", state.seek(fd, offset, whence))
+                    .unwrap_or_else(|e| e.code().into()))
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.stat",
+            move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
+                let path = AsMem::for_caller(&caller, abi)?.decode_str(path_ptr)?;
+                let stat = match state.check("stat", state.stat(&path)) {
+                    Ok(stat) => stat,
+                    Err(_) => return Ok(0),
+                };
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_bytes(&stat)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.list",
+            move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
+                let path = AsMem::for_caller(&caller, abi)?.decode_str(path_ptr)?;
+                let names = match state.check("list", state.list(&path)) {
+                    Ok(names) => names,
+                    Err(_) => return Ok(0),
+                };
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&names)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.remove",
+            move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
+                let path = AsMem::for_caller(&caller, abi)?.decode_str(path_ptr)?;
+                Ok(state.status("remove", state.remove(&path)))
             },
         )?;
     }
 
     {
-        // let state = state.clone();
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.mkdir",
+            move |caller: Caller, path_ptr: i32| -> Result<i32, Trap> {
+                let path = AsMem::for_caller(&caller, abi)?.decode_str(path_ptr)?;
+                Ok(state.status("mkdir", state.mkdir(&path)))
+            },
+        )?;
+    }
+
+    {
+        let state = state.clone();
         linker.func(
             module,
             "io.close",
             move |_caller: Caller, fd: i32| -> Result<(), Trap> {
-                state.close(fd);
+                let _ = state.check("close", state.close(fd));
                 Ok(())
             },
         )?;
     }
-    Ok(())
+
+    {
+        let state = state.clone();
+        linker.func(
+            module,
+            "io.lastError",
+            move |caller: Caller| -> Result<i32, Trap> {
+                let message = match state.last_error() {
+                    Some(message) => message,
+                    None => return Ok(0),
+                };
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&message)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(base_dir: &Path) -> FdStateInner {
+        let vol = |name: &str, path: &str, mode: AccessMode| {
+            std::fs::create_dir_all(base_dir.join(name)).unwrap();
+            Volume {
+                name: name.to_string(),
+                path: path.to_string(),
+                mode,
+                quota: None,
+            }
+        };
+        FdStateInner {
+            vols: vec![
+                vol("vol-a", "/data", AccessMode::Rw),
+                vol("vol-b", "/data/sub/", AccessMode::Private),
+                vol("vol-c", "/in/", AccessMode::Ro),
+                vol("vol-d", "/out/", AccessMode::Wo),
+            ],
+            base_dir: base_dir.to_owned(),
+            limits: IoLimits {
+                max_fds: 2,
+                max_file_size: Some(4),
+                max_volume_size: None,
+            },
+            usage: Default::default(),
+            fds: Default::default(),
+            n_fds: MIN_FD,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn test_find_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let state = state(&base);
+        let find = |path: &str| state.find_path(path).unwrap();
+
+        assert_eq!(
+            find("/data/result.json"),
+            Some(base.join("vol-a/result.json"))
+        );
+        assert_eq!(find("/data/./a//b.txt"), Some(base.join("vol-a/a/b.txt")));
+        assert_eq!(find("/data"), Some(base.join("vol-a")));
+        assert_eq!(find("/data/sub/x"), Some(base.join("vol-b/x")));
+        assert_eq!(find("/data/subx"), Some(base.join("vol-a/subx")));
+        assert_eq!(find("/database/x"), None);
+        assert_eq!(find("/etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let state = state(&base);
+
+        for path in &[
+            "/data/../etc/passwd",
+            "/data/sub/../../vol-b",
+            "/data/..",
+            "/../data/x",
+            "data/x",
+            "",
+            "/data/a:b",
+            "/data/..\\x",
+            "/data/x\0",
+        ] {
+            assert!(state.find_path(path).is_err(), "{:?} accepted", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let state = state(&base);
+        std::fs::create_dir(base.join("vol-a/inner")).unwrap();
+        symlink(outside.path(), base.join("vol-a/out")).unwrap();
+        symlink(base.join("vol-a/inner"), base.join("vol-a/in")).unwrap();
+        symlink(outside.path().join("missing"), base.join("vol-a/dangling")).unwrap();
+        symlink(base.join("vol-b"), base.join("vol-a/other-vol")).unwrap();
+
+        assert!(state.find_path("/data/out").is_err());
+        assert!(state.find_path("/data/out/new.txt").is_err());
+        assert!(state.find_path("/data/dangling").is_err());
+        assert!(state.find_path("/data/other-vol/x").is_err());
+        assert_eq!(
+            state.find_path("/data/in/x").unwrap(),
+            Some(base.join("vol-a/in/x"))
+        );
+    }
+
+    #[test]
+    fn test_access_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let state = state(&base);
+        let allowed = |path: &str, access: Access| match state.find_path_for(path, access) {
+            Ok(_) => true,
+            Err(e) if IoError::classify(&e) == IoError::AccessDenied => false,
+            Err(e) => panic!("{}", e),
+        };
+
+        assert!(allowed("/data/x", Access::Write));
+        assert!(allowed("/data/sub/x", Access::Read));
+        assert!(allowed("/data/sub/x", Access::Write));
+        assert!(allowed("/in/x", Access::Read));
+        assert!(!allowed("/in/x", Access::Write));
+        assert!(allowed("/out/x", Access::Write));
+        assert!(!allowed("/out/x", Access::Read));
+    }
+
+    #[test]
+    fn test_keystore_hidden() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        std::fs::create_dir(base.join("vol-b").join(KEYSTORE_DIR)).unwrap();
+        std::fs::write(base.join("vol-b").join(KEYSTORE_DIR).join("key"), b"k").unwrap();
+        std::fs::create_dir(base.join("vol-a").join(KEYSTORE_DIR)).unwrap();
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        assert_eq!(
+            code("ropen", state.open_read("/data/sub/.keystore/key")),
+            -2
+        );
+        assert_eq!(
+            code("wopen", state.open_write("/data/sub/.keystore/x", false)),
+            -2
+        );
+        assert_eq!(code("remove", state.remove("/data/sub/.keystore/key")), -2);
+        assert_eq!(code("remove", state.remove("/data/sub/./.keystore")), -2);
+        assert!(state.list("/data/sub/.keystore").is_err());
+        assert_eq!(state.list("/data/sub").unwrap(), "");
+        // only private volumes hold a keystore
+        assert_eq!(state.list("/data").unwrap(), ".keystore/");
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        assert_eq!(state.last_error(), None);
+        assert_eq!(code("ropen", state.open_read("/data/missing")), -3);
+        assert!(state.last_error().unwrap().starts_with("ropen: "));
+        assert_eq!(code("ropen", state.open_read("/etc/passwd")), -7);
+        assert_eq!(code("ropen", state.open_read("/data/../x")), -6);
+        assert_eq!(code("wopen", state.open_write("/in/x", false)), -2);
+        assert_eq!(code("remove", state.remove("/data")), -2);
+        assert_eq!(code("read", state.read(MIN_FD, &mut [0; 4])), -8);
+
+        let fd = code("wopen", state.open_write("/data/x", false));
+        assert!(fd >= MIN_FD);
+        assert_eq!(code("write", state.write(fd, b"abc")), 3);
+        assert_eq!(state.size(fd).unwrap(), 3);
+        assert!(state.close(fd).is_ok());
+        assert_eq!(state.check("close", state.close(fd)), Err(IoError::BadFd));
+    }
+
+    #[test]
+    fn test_stat() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        std::fs::write(base.join("vol-c/in.txt"), b"abc").unwrap();
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let field = |stat: &[u8; STAT_SIZE], range: std::ops::Range<usize>| {
+            let mut bytes = [0u8; 8];
+            bytes[..range.len()].copy_from_slice(&stat[range]);
+            u64::from_le_bytes(bytes)
+        };
+
+        let stat = state.stat("/in/in.txt").unwrap();
+        assert_eq!(field(&stat, 0..4), KIND_FILE as u64);
+        assert_eq!(field(&stat, 8..16), 3);
+        assert!(field(&stat, 16..24) > 0);
+        let stat = state.stat("/in").unwrap();
+        assert_eq!(field(&stat, 0..4), KIND_DIR as u64);
+
+        let code = |path: &str| state.check("stat", state.stat(path)).map(|_| ());
+        assert_eq!(code("/in/missing"), Err(IoError::NotFound));
+        assert_eq!(code("/out/x"), Err(IoError::AccessDenied));
+        assert_eq!(code("/etc/passwd"), Err(IoError::NoVolume));
+        assert!(state.last_error().unwrap().starts_with("stat: "));
+    }
+
+    #[test]
+    fn test_read_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        std::fs::write(base.join("vol-c/in.txt"), b"abc").unwrap();
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        let fd = code("ropen", state.open_read("/in/in.txt"));
+        assert_eq!(state.read_all(fd).unwrap(), b"abc");
+        // reads from the current position
+        assert_eq!(state.seek(fd, 1, 0).unwrap(), 1);
+        assert_eq!(state.read_all(fd).unwrap(), b"bc");
+        assert!(state.close(fd).is_ok());
+        assert_eq!(
+            state.check("readAll", state.read_all(fd)),
+            Err(IoError::BadFd)
+        );
+
+        assert_eq!(code("ropen", state.open_read("/in/missing")), -3);
+        assert_eq!(code("ropen", state.open_read("/out/x")), -2);
+    }
+
+    #[test]
+    fn test_mkdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        assert_eq!(code("mkdir", state.mkdir("/data/a/b")), 0);
+        assert!(base.join("vol-a/a/b").is_dir());
+        // existing directories are accepted
+        assert_eq!(code("mkdir", state.mkdir("/data/a")), 0);
+        assert_eq!(code("mkdir", state.mkdir("/out/a")), 0);
+
+        assert_eq!(code("mkdir", state.mkdir("/in/a")), -2);
+        assert!(!base.join("vol-c/a").exists());
+        assert_eq!(code("mkdir", state.mkdir("/etc/a")), -7);
+        assert_eq!(code("mkdir", state.mkdir("/data/../a")), -6);
+        std::fs::write(base.join("vol-a/file"), b"").unwrap();
+        assert_eq!(code("mkdir", state.mkdir("/data/file")), -5);
+    }
+
+    #[test]
+    fn test_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let inner = state(&base);
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        let fd = code("wopen", state.open_write("/data/x", false));
+        assert_eq!(code("write", state.write(fd, b"abc")), 3);
+        assert_eq!(code("write", state.write(fd, b"de")), -11);
+        assert_eq!(state.seek(fd, 0, 0).unwrap(), 0);
+        assert_eq!(code("write", state.write(fd, b"xyz")), 3);
+        let afd = code("aopen", state.open_write("/data/x", true));
+        assert_eq!(code("write", state.write(afd, b"1")), 1);
+        assert_eq!(code("write", state.write(afd, b"2")), -11);
+        assert_eq!(code("ropen", state.open_read("/data/x")), -9);
+    }
+
+    #[test]
+    fn test_quota() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let mut inner = state(&base);
+        inner.vols[0].quota = Some(6);
+        inner.limits.max_volume_size = Some(8);
+        std::fs::write(base.join("vol-a/old"), b"abc").unwrap();
+        let state = FdState::new(inner.base_dir, inner.vols, inner.limits);
+        let code = |op: &str, result: std::io::Result<i32>| state.status(op, result);
+
+        let fd = code("wopen", state.open_write("/data/x", false));
+        assert_eq!(code("write", state.write(fd, b"abc")), 3);
+        assert_eq!(code("write", state.write(fd, b"d")), -11);
+        assert_eq!(code("remove", state.remove("/data/old")), 0);
+        assert_eq!(code("write", state.write(fd, b"d")), 1);
+        assert!(state.close(fd).is_ok());
+        let fd = code("wopen", state.open_write("/data/x", false));
+        assert_eq!(code("write", state.write(fd, b"12")), 2);
+
+        let usage = state.volume_usage().unwrap();
+        assert_eq!(usage[0].used, 2);
+        assert_eq!(usage[0].quota, Some(6));
+        assert_eq!(usage[3].quota, Some(8));
+    }
 }
@@ -0,0 +1,281 @@
+use super::aead::{open, seal};
+use super::eth::{tx::TxRequest, ToEthAddress};
+use super::{AbiVersion, Allocator, AsMem};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::NewAead;
+use aes_gcm::Aes256Gcm;
+use anyhow::{anyhow, bail, Context};
+use secp256k1::{PublicKey, SecretKey, SharedSecret};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use wasmtime::{Caller, Linker, Trap};
+
+/// Environment variable with hex encoded 32 byte key used to encrypt stored keys.
+pub const KEYSTORE_KEY_ENV: &str = "YA_RUNTIME_ASWASM_KEYSTORE_KEY";
+/// Keystore directory inside the private volume, not reachable with `io.*` functions.
+pub(super) const KEYSTORE_DIR: &str = ".keystore";
+const HANDLE_LEN: usize = 32;
+
+/// Host side storage of guest secp256k1 keys.
+///
+/// Keys are encrypted with AES-256-GCM under a provider supplied key and
+/// stored one per file, guests refer to them by opaque handles.
+pub struct Keystore {
+    dir: PathBuf,
+    cipher: Aes256Gcm,
+}
+
+impl Keystore {
+    pub fn new(dir: PathBuf, key: &[u8; 32]) -> Self {
+        let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+        Keystore { dir, cipher }
+    }
+
+    /// Opens keystore in a private volume, with key taken from [`KEYSTORE_KEY_ENV`].
+    pub fn from_env(vol_dir: &Path) -> anyhow::Result<Self> {
+        let hex_key = std::env::var(KEYSTORE_KEY_ENV)
+            .with_context(|| format!("{} is not set", KEYSTORE_KEY_ENV))?;
+        let mut key = [0u8; 32];
+        hex::decode_to_slice(hex_key.trim(), &mut key).with_context(|| {
+            format!(
+                "invalid {}, expected 32 hex encoded bytes",
+                KEYSTORE_KEY_ENV
+            )
+        })?;
+        Ok(Self::new(vol_dir.join(KEYSTORE_DIR), &key))
+    }
+
+    pub fn generate(&self) -> anyhow::Result<String> {
+        self.import(&SecretKey::random(&mut rand::thread_rng()))
+    }
+
+    pub fn import(&self, secret: &SecretKey) -> anyhow::Result<String> {
+        let handle = uuid::Uuid::new_v4().to_simple().to_string();
+        let sealed = seal(self.cipher.clone(), &secret.serialize(), handle.as_bytes())?;
+        std::fs::create_dir_all(&self.dir)?;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(self.key_path(&handle)?)?.write_all(&sealed)?;
+        Ok(handle)
+    }
+
+    pub fn secret(&self, handle: &str) -> anyhow::Result<SecretKey> {
+        let sealed = std::fs::read(self.key_path(handle)?)
+            .with_context(|| format!("unknown key: {}", handle))?;
+        let bytes = open(self.cipher.clone(), &sealed, handle.as_bytes())
+            .ok_or_else(|| anyhow!("unable to decrypt key: {}", handle))?;
+        SecretKey::parse_slice(&bytes).map_err(|e| anyhow!("invalid key {}: {}", handle, e))
+    }
+
+    pub fn remove(&self, handle: &str) -> anyhow::Result<bool> {
+        match std::fs::remove_file(self.key_path(handle)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn key_path(&self, handle: &str) -> anyhow::Result<PathBuf> {
+        if handle.len() != HANDLE_LEN || !handle.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("invalid key handle: {:?}", handle);
+        }
+        Ok(self.dir.join(handle))
+    }
+}
+
+fn get(keystore: &Option<Keystore>) -> Result<&Keystore, Trap> {
+    keystore
+        .as_ref()
+        .ok_or_else(|| Trap::new("keystore is not available"))
+}
+
+fn to_trap(e: anyhow::Error) -> Trap {
+    Trap::new(format!("keystore: {:#}", e))
+}
+
+/*
+export declare namespace keys {
+  type Handle = string;
+  export function generate(): Handle;
+  export function import(prvKey: ArrayBuffer): Handle;
+  export function publicKey(key: Handle): ArrayBuffer;
+  export function address(key: Handle): string;
+  export function sign(key: Handle, messageHash: ArrayBuffer): ArrayBuffer;
+  export function signTx(key: Handle, tx: string): ArrayBuffer;
+  export function sharedSecret(key: Handle, pubKey: ArrayBuffer): ArrayBuffer;
+  export function remove(key: Handle): bool;
+}
+*/
+
+/// Registers `keys.*` host functions.
+///
+/// Without a keystore functions are still linked, but trap when called.
+pub fn link_keystore(
+    module: &str,
+    linker: &mut Linker,
+    abi: Option<AbiVersion>,
+    keystore: Option<Keystore>,
+) -> anyhow::Result<()> {
+    let keystore = Rc::new(keystore);
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.generate",
+            move |caller: Caller| -> Result<i32, Trap> {
+                let handle = get(&keystore)?.generate().map_err(to_trap)?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&handle)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.import",
+            move |caller: Caller, prv_ptr: i32| -> Result<i32, Trap> {
+                let secret = AsMem::for_caller(&caller, abi)?.decode_secret(prv_ptr)?;
+                let handle = get(&keystore)?.import(&secret).map_err(to_trap)?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&handle)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.publicKey",
+            move |caller: Caller, handle_ptr: i32| -> Result<i32, Trap> {
+                let handle = AsMem::for_caller(&caller, abi)?.decode_str(handle_ptr)?;
+                let secret = get(&keystore)?.secret(&handle).map_err(to_trap)?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator
+                    .new_bytes(PublicKey::from_secret_key(&secret).serialize().as_ref())?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.address",
+            move |caller: Caller, handle_ptr: i32| -> Result<i32, Trap> {
+                let handle = AsMem::for_caller(&caller, abi)?.decode_str(handle_ptr)?;
+                let secret = get(&keystore)?.secret(&handle).map_err(to_trap)?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_string(&secret.to_eth_address().to_hex_string())?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.sign",
+            move |caller: Caller, handle_ptr: i32, hash_ptr: i32| -> Result<i32, Trap> {
+                let mem = AsMem::for_caller(&caller, abi)?;
+                let handle = mem.decode_str(handle_ptr)?;
+                let hash = mem.decode_hash(hash_ptr)?;
+                let secret = get(&keystore)?.secret(&handle).map_err(to_trap)?;
+                let bytes = hash.sign_by(&secret).serialize_rpc();
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_bytes(&bytes)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.signTx",
+            move |caller: Caller, handle_ptr: i32, tx_ptr: i32| -> Result<i32, Trap> {
+                let mem = AsMem::for_caller(&caller, abi)?;
+                let handle = mem.decode_str(handle_ptr)?;
+                let tx: TxRequest = serde_json::from_str(&mem.decode_str(tx_ptr)?)
+                    .map_err(|e| Trap::new(format!("invalid transaction: {}", e)))?;
+                let secret = get(&keystore)?.secret(&handle).map_err(to_trap)?;
+                let bytes = tx
+                    .sign(&secret)
+                    .map_err(|e| Trap::new(format!("invalid transaction: {}", e)))?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_bytes(&bytes)?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    {
+        let keystore = keystore.clone();
+        linker.func(
+            module,
+            "keys.sharedSecret",
+            move |caller: Caller, handle_ptr: i32, pub_ptr: i32| -> Result<i32, Trap> {
+                let mem = AsMem::for_caller(&caller, abi)?;
+                let handle = mem.decode_str(handle_ptr)?;
+                let pubkey = mem.decode_pubkey(pub_ptr)?;
+                let secret = get(&keystore)?.secret(&handle).map_err(to_trap)?;
+                let shared_secret: SharedSecret<sha2::Sha256> =
+                    SharedSecret::new(&pubkey, &secret).map_err(|e| Trap::new(e.to_string()))?;
+                let mut allocator = Allocator::for_caller(&caller, abi)?;
+                let ptr = allocator.new_bytes(shared_secret.as_ref())?;
+                allocator.retain(ptr)
+            },
+        )?;
+    }
+
+    linker.func(
+        module,
+        "keys.remove",
+        move |caller: Caller, handle_ptr: i32| -> Result<i32, Trap> {
+            let handle = AsMem::for_caller(&caller, abi)?.decode_str(handle_ptr)?;
+            let removed = get(&keystore)?.remove(&handle).map_err(to_trap)?;
+            Ok(removed as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let keystore = Keystore::new(dir.path().to_owned(), &[7; 32]);
+        let secret = SecretKey::random(&mut rand::thread_rng());
+        let handle = keystore.import(&secret).unwrap();
+        assert_eq!(keystore.secret(&handle).unwrap(), secret);
+
+        let stored = std::fs::read(dir.path().join(&handle)).unwrap();
+        assert!(!stored.windows(32).any(|w| w == secret.serialize().as_ref()));
+
+        let other = Keystore::new(dir.path().to_owned(), &[8; 32]);
+        assert!(other.secret(&handle).is_err());
+        assert!(keystore.secret("../../etc/passwd").is_err());
+
+        assert!(keystore.remove(&handle).unwrap());
+        assert!(!keystore.remove(&handle).unwrap());
+    }
+}
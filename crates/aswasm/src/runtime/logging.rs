@@ -0,0 +1,163 @@
+use super::{AbiVersion, AsMem};
+use log::{Level, LevelFilter};
+use serde_json::{Map, Value};
+use std::rc::Rc;
+use std::str::FromStr;
+use wasmtime::{Caller, Linker, Trap};
+
+/// Minimum level of forwarded guest logs (`error`, `warn`, `info`, `debug`, `trace` or `off`).
+pub const LOG_LEVEL_ENV: &str = "YA_RUNTIME_ASWASM_LOG_LEVEL";
+/// When set to `1` or `true` guest logs are also written to the process stderr stream.
+pub const LOG_STDERR_ENV: &str = "YA_RUNTIME_ASWASM_LOG_STDERR";
+
+/// Guest log routing.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    /// Log target, guest logs of each app can be filtered separately.
+    pub target: String,
+    pub min_level: LevelFilter,
+    pub mirror_stderr: bool,
+}
+
+impl LogConfig {
+    pub fn new(app_name: &str) -> Self {
+        let app_name = if app_name.is_empty() { "app" } else { app_name };
+        LogConfig {
+            target: format!("aswasm::{}", app_name),
+            min_level: LevelFilter::Info,
+            mirror_stderr: false,
+        }
+    }
+
+    pub fn from_env(app_name: &str) -> anyhow::Result<Self> {
+        let mut config = Self::new(app_name);
+        if let Ok(level) = std::env::var(LOG_LEVEL_ENV) {
+            config.min_level = LevelFilter::from_str(level.trim())
+                .map_err(|_| anyhow::anyhow!("invalid {}: {:?}", LOG_LEVEL_ENV, level))?;
+        }
+        if let Ok(flag) = std::env::var(LOG_STDERR_ENV) {
+            config.mirror_stderr = matches!(flag.trim(), "1" | "true");
+        }
+        Ok(config)
+    }
+
+    fn log(&self, level: Level, message: &str, stderr: &dyn Fn(&[u8])) {
+        if level > self.min_level {
+            return;
+        }
+        // Goes directly to the logger, guest logs are not subject to
+        // the compile time max level of the host.
+        log::logger().log(
+            &log::Record::builder()
+                .level(level)
+                .target(&self.target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+        if self.mirror_stderr {
+            stderr(format!("[{}] {}\n", level, message).as_bytes());
+        }
+    }
+}
+
+fn decode_level(level: i32) -> Result<Level, Trap> {
+    Ok(match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        5 => Level::Trace,
+        _ => return Err(Trap::new(format!("invalid log level: {}", level))),
+    })
+}
+
+fn format_kv(message: &str, fields: &Map<String, Value>) -> String {
+    let mut output = message.to_string();
+    for (key, value) in fields {
+        output.push(' ');
+        output.push_str(key);
+        output.push('=');
+        match value {
+            Value::String(s) => output.push_str(&format!("{:?}", s)),
+            value => output.push_str(&value.to_string()),
+        }
+    }
+    output
+}
+
+/*
+export declare namespace ya {
+  // 1 - error, 2 - warn, 3 - info, 4 - debug, 5 - trace
+  export function logLevel(level: i32, message: string): void;
+  // fields: JSON object, appended to message as key=value pairs.
+  export function logKv(level: i32, message: string, fields: string): void;
+}
+*/
+
+/// Registers `logLevel` and `logKv` host functions.
+///
+/// `stderr` receives mirrored messages when [`LogConfig::mirror_stderr`] is set.
+pub fn link_logging(
+    module: &str,
+    linker: &mut Linker,
+    abi: Option<AbiVersion>,
+    config: LogConfig,
+    stderr: impl Fn(&[u8]) + 'static,
+) -> anyhow::Result<()> {
+    let config = Rc::new(config);
+    let stderr: Rc<dyn Fn(&[u8])> = Rc::new(stderr);
+
+    {
+        let config = config.clone();
+        let stderr = stderr.clone();
+        linker.func(
+            module,
+            "logLevel",
+            move |caller: Caller, level: i32, message_ptr: i32| -> Result<(), Trap> {
+                let level = decode_level(level)?;
+                let message = AsMem::for_caller(&caller, abi)?.decode_str(message_ptr)?;
+                config.log(level, &message, stderr.as_ref());
+                Ok(())
+            },
+        )?;
+    }
+
+    linker.func(
+        module,
+        "logKv",
+        move |caller: Caller, level: i32, message_ptr: i32, fields_ptr: i32| -> Result<(), Trap> {
+            let level = decode_level(level)?;
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let message = mem.decode_str(message_ptr)?;
+            let fields: Map<String, Value> = serde_json::from_str(&mem.decode_str(fields_ptr)?)
+                .map_err(|e| Trap::new(format!("invalid log fields: {}", e)))?;
+            config.log(level, &format_kv(&message, &fields), stderr.as_ref());
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_min_level_and_mirror() {
+        let mut config = LogConfig::new("MyApp");
+        assert_eq!(config.target, "aswasm::MyApp");
+        config.min_level = LevelFilter::Warn;
+        config.mirror_stderr = true;
+
+        let out = RefCell::new(Vec::new());
+        let stderr = |bytes: &[u8]| out.borrow_mut().extend_from_slice(bytes);
+        config.log(Level::Info, "skipped", &stderr);
+        config.log(Level::Error, "failed", &stderr);
+        assert_eq!(String::from_utf8_lossy(&out.borrow()), "[ERROR] failed\n");
+
+        let fields = serde_json::from_str(r#"{"n": 1, "who": "bob"}"#).unwrap();
+        assert_eq!(format_kv("done", &fields), r#"done n=1 who="bob""#);
+    }
+}
@@ -0,0 +1,441 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use wasmtime::{Extern, Memory, Trap};
+
+// Typeinfo flags shared by all supported runtime versions.
+const ARRAYBUFFERVIEW: u32 = 1 << 0;
+const ARRAY: u32 = 1 << 1;
+const STATICARRAY: u32 = 1 << 2;
+const SET: u32 = 1 << 3;
+const MAP: u32 = 1 << 4;
+const CONTAINER: u32 = ARRAYBUFFERVIEW | ARRAY | STATICARRAY | SET | MAP;
+// Bit 5 is ACYCLIC in 0.17 and POINTERFREE since 0.18, value flags follow it.
+const VALUE_ALIGN_OFFSET: u32 = 6;
+const VALUE_SIGNED: u32 = 1 << 11;
+
+// Offsets of `ArrayBufferView` fields.
+const VIEW_DATASTART_OFFSET: usize = 4;
+const VIEW_BYTELENGTH_OFFSET: usize = 8;
+/// Offset of runtime class id in object header, relative to object pointer.
+const RT_ID_OFFSET: usize = 8;
+
+/// Byte collection classes guests may pass to host functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesClass {
+    Uint8Array,
+    StaticArrayU8,
+}
+
+impl BytesClass {
+    /// Name of an exported global guests can use to declare class id,
+    /// eg. `export const Uint8Array_ID = idof<Uint8Array>();`
+    fn id_export(self) -> &'static str {
+        match self {
+            Self::Uint8Array => "Uint8Array_ID",
+            Self::StaticArrayU8 => "StaticArrayU8_ID",
+        }
+    }
+}
+
+/// AssemblyScript runtime ABI the module was compiled against.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiVersion {
+    /// `asc` 0.10 - 0.17: reference counting runtime (`__retain`/`__release`).
+    #[serde(rename = "0.17")]
+    V0_17,
+    /// `asc` 0.18 - 0.19: tracing runtime (`__pin`/`__unpin`/`__collect`).
+    #[serde(rename = "0.18")]
+    V0_18,
+    /// `asc` 0.20 and newer: tracing runtime with `Object` as the base class.
+    #[serde(rename = "0.20")]
+    V0_20,
+}
+
+impl AbiVersion {
+    const LATEST: Self = Self::V0_20;
+
+    /// Detects runtime ABI from module exports.
+    ///
+    /// Exports only tell reference counting and tracing runtimes apart, so
+    /// tracing modules are assumed to be built by the latest compiler unless
+    /// the manifest pins another version.
+    fn detect(
+        has_export: impl Fn(&str) -> bool,
+        pinned: Option<Self>,
+    ) -> Result<Option<Self>, Trap> {
+        let tracing = if has_export("__retain") {
+            false
+        } else if has_export("__pin") {
+            true
+        } else {
+            return Ok(None);
+        };
+        match pinned {
+            None if tracing => Ok(Some(Self::LATEST)),
+            None => Ok(Some(Self::V0_17)),
+            Some(version) if version.is_tracing() == tracing => Ok(Some(version)),
+            Some(version) => Err(Trap::new(format!(
+                "module runtime exports do not match pinned AssemblyScript ABI {}",
+                version
+            ))),
+        }
+    }
+
+    pub fn is_tracing(self) -> bool {
+        self != Self::V0_17
+    }
+
+    fn array_buffer_id(self) -> u32 {
+        match self {
+            Self::V0_17 | Self::V0_18 => 0,
+            Self::V0_20 => 1,
+        }
+    }
+
+    fn string_id(self) -> u32 {
+        match self {
+            Self::V0_17 | Self::V0_18 => 1,
+            Self::V0_20 => 2,
+        }
+    }
+
+    /// Size of a single `__rtti_base` table entry.
+    fn rtti_stride(self) -> usize {
+        match self {
+            // flags, base class id
+            Self::V0_17 | Self::V0_18 => 8,
+            // flags
+            Self::V0_20 => 4,
+        }
+    }
+}
+
+impl fmt::Display for AbiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V0_17 => "0.17",
+            Self::V0_18 => "0.18",
+            Self::V0_20 => "0.20",
+        })
+    }
+}
+
+/// Runtime layout of an instantiated AssemblyScript module.
+pub(super) struct Layout {
+    pub version: AbiVersion,
+    rtti_base: Option<u32>,
+    pub array_buffer_id: u32,
+    pub string_id: u32,
+    uint8_array_id: Option<u32>,
+    static_array_u8_id: Option<u32>,
+}
+
+impl Layout {
+    pub fn resolve(
+        exports: &impl Fn(&str) -> Option<Extern>,
+        mem: &Memory,
+        pinned: Option<AbiVersion>,
+    ) -> Result<Option<Self>, Trap> {
+        let version = match AbiVersion::detect(|name| exports(name).is_some(), pinned)? {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let layout = Layout {
+            version,
+            rtti_base: global_u32(exports, "__rtti_base")?,
+            array_buffer_id: global_u32(exports, "ArrayBuffer_ID")?
+                .unwrap_or_else(|| version.array_buffer_id()),
+            string_id: global_u32(exports, "String_ID")?.unwrap_or_else(|| version.string_id()),
+            uint8_array_id: global_u32(exports, BytesClass::Uint8Array.id_export())?,
+            static_array_u8_id: global_u32(exports, BytesClass::StaticArrayU8.id_export())?,
+        };
+        for (class_name, id) in &[
+            ("ArrayBuffer", layout.array_buffer_id),
+            ("String", layout.string_id),
+        ] {
+            if let Some(flags) = layout.flags(mem, *id)? {
+                if flags & CONTAINER != 0 {
+                    return Err(Trap::new(format!(
+                        "class id {} is not {} in AssemblyScript ABI {}, pin ABI version in manifest",
+                        id, class_name, version
+                    )));
+                }
+            }
+        }
+        Ok(Some(layout))
+    }
+
+    /// Reads typeinfo flags of the given class id, if module exports RTTI.
+    pub fn flags(&self, mem: &Memory, id: u32) -> Result<Option<u32>, Trap> {
+        let base = match self.rtti_base {
+            Some(base) => base as usize,
+            None => return Ok(None),
+        };
+        let count = read_u32(mem, base)?;
+        if id >= count {
+            return Err(Trap::new(format!(
+                "invalid class id: {} (rtti count: {})",
+                id, count
+            )));
+        }
+        read_u32(mem, base + 4 + id as usize * self.version.rtti_stride()).map(Some)
+    }
+
+    /// Locates data of an array buffer view or `Array` object.
+    ///
+    /// Returns `None` for objects storing data inline, such as `ArrayBuffer` and `StaticArray`.
+    /// Classes other than the byte collections declared in exports are looked up in RTTI.
+    pub fn view_data(&self, mem: &Memory, ptr: usize) -> Result<Option<(usize, usize)>, Trap> {
+        if ptr < RT_ID_OFFSET {
+            return Err(Trap::new(format!("invalid allocation pointer: {}", ptr)));
+        }
+        let id = read_u32(mem, ptr - RT_ID_OFFSET)?;
+        if id == self.array_buffer_id || Some(id) == self.static_array_u8_id {
+            return Ok(None);
+        }
+        let is_view = if Some(id) == self.uint8_array_id {
+            true
+        } else {
+            match self.flags(mem, id)? {
+                Some(flags) => flags & (ARRAYBUFFERVIEW | ARRAY) != 0,
+                None => {
+                    return Err(Trap::new(format!(
+                        "unknown class id {}, module does not export __rtti_base",
+                        id
+                    )))
+                }
+            }
+        };
+        if !is_view {
+            return Ok(None);
+        }
+        let data_start = read_u32(mem, ptr + VIEW_DATASTART_OFFSET)? as usize;
+        let byte_length = read_u32(mem, ptr + VIEW_BYTELENGTH_OFFSET)? as usize;
+        Ok(Some((data_start, byte_length)))
+    }
+}
+
+fn global_u32(exports: &impl Fn(&str) -> Option<Extern>, name: &str) -> Result<Option<u32>, Trap> {
+    match exports(name).and_then(Extern::into_global) {
+        Some(global) => global
+            .get()
+            .i32()
+            .map(|v| Some(v as u32))
+            .ok_or_else(|| Trap::new(format!("invalid type of \"{}\" global", name))),
+        None => Ok(None),
+    }
+}
+
+fn read_u32(mem: &Memory, addr: usize) -> Result<u32, Trap> {
+    let data = unsafe { mem.data_unchecked() };
+    let b = data
+        .get(addr..addr + 4)
+        .ok_or_else(|| Trap::new(format!("out of bounds memory access: {:#x}", addr)))?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use wasmtime::{Func, Global, GlobalType, Limits, MemoryType, Mutability, Store, Val, ValType};
+
+    fn write_u32(mem: &Memory, addr: usize, value: u32) -> Result<(), Trap> {
+        let data = unsafe { mem.data_unchecked_mut() };
+        data.get_mut(addr..addr + 4)
+            .ok_or_else(|| Trap::new(format!("out of bounds memory access: {:#x}", addr)))?
+            .copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    const RC_EXPORTS: &[&str] = &["__new", "__retain", "__release"];
+    const TRACING_EXPORTS: &[&str] = &["__new", "__pin", "__unpin", "__collect"];
+
+    fn exports(
+        store: &Store,
+        funcs: &[&str],
+        globals: &[(&str, u32)],
+    ) -> impl Fn(&str) -> Option<Extern> {
+        let mut exports = HashMap::new();
+        for name in funcs {
+            exports.insert(name.to_string(), Extern::from(Func::wrap(store, || ())));
+        }
+        for (name, value) in globals {
+            let ty = GlobalType::new(ValType::I32, Mutability::Const);
+            let global = Global::new(store, ty, Val::I32(*value as i32)).unwrap();
+            exports.insert(name.to_string(), Extern::from(global));
+        }
+        move |name: &str| exports.get(name).cloned()
+    }
+
+    /// Writes `__rtti_base` table with given class flags at `base`.
+    fn write_rtti(mem: &Memory, version: AbiVersion, base: u32, flags: &[u32]) {
+        write_u32(mem, base as usize, flags.len() as u32).unwrap();
+        for (id, flags) in flags.iter().enumerate() {
+            let entry = base as usize + 4 + id * version.rtti_stride();
+            write_u32(mem, entry, *flags).unwrap();
+        }
+    }
+
+    fn resolve(
+        exports: &impl Fn(&str) -> Option<Extern>,
+        mem: &Memory,
+        pinned: Option<AbiVersion>,
+    ) -> Result<Option<AbiVersion>, Trap> {
+        Layout::resolve(exports, mem, pinned).map(|layout| layout.map(|layout| layout.version))
+    }
+
+    #[test]
+    fn test_resolve_version() {
+        let store = Store::default();
+        let mem = Memory::new(&store, MemoryType::new(Limits::new(1, None)));
+
+        assert_eq!(
+            resolve(&exports(&store, &[], &[]), &mem, None).unwrap(),
+            None
+        );
+
+        let rc = exports(&store, RC_EXPORTS, &[]);
+        assert_eq!(resolve(&rc, &mem, None).unwrap(), Some(AbiVersion::V0_17));
+        assert!(resolve(&rc, &mem, Some(AbiVersion::V0_18)).is_err());
+        assert!(resolve(&rc, &mem, Some(AbiVersion::V0_20)).is_err());
+
+        let tracing = exports(&store, TRACING_EXPORTS, &[]);
+        assert_eq!(
+            resolve(&tracing, &mem, None).unwrap(),
+            Some(AbiVersion::V0_20)
+        );
+        assert_eq!(
+            resolve(&tracing, &mem, Some(AbiVersion::V0_18)).unwrap(),
+            Some(AbiVersion::V0_18)
+        );
+        assert!(resolve(&tracing, &mem, Some(AbiVersion::V0_17)).is_err());
+    }
+
+    /// Class ids and flags of byte collections in tables emitted by `asc`.
+    struct Rtti {
+        version: AbiVersion,
+        exports: &'static [&'static str],
+        flags: &'static [u32],
+        uint8_array_id: u32,
+        static_array_u8_id: u32,
+    }
+
+    const INT8_ARRAY: u32 = ARRAYBUFFERVIEW | (1 << VALUE_ALIGN_OFFSET) | VALUE_SIGNED;
+    const UINT8_ARRAY: u32 = ARRAYBUFFERVIEW | (1 << VALUE_ALIGN_OFFSET);
+    const STATIC_ARRAY_U8: u32 = STATICARRAY | (1 << VALUE_ALIGN_OFFSET);
+    const ACYCLIC: u32 = 1 << 5;
+    const POINTERFREE: u32 = 1 << 5;
+
+    const RTTI: &[Rtti] = &[
+        // ArrayBuffer, String, ArrayBufferView, Int8Array, Uint8Array, StaticArray<u8>
+        Rtti {
+            version: AbiVersion::V0_17,
+            exports: RC_EXPORTS,
+            flags: &[
+                ACYCLIC,
+                ACYCLIC,
+                ACYCLIC,
+                INT8_ARRAY | ACYCLIC,
+                UINT8_ARRAY | ACYCLIC,
+                STATIC_ARRAY_U8 | ACYCLIC,
+            ],
+            uint8_array_id: 4,
+            static_array_u8_id: 5,
+        },
+        Rtti {
+            version: AbiVersion::V0_18,
+            exports: TRACING_EXPORTS,
+            flags: &[
+                POINTERFREE,
+                POINTERFREE,
+                0,
+                INT8_ARRAY,
+                UINT8_ARRAY,
+                STATIC_ARRAY_U8 | POINTERFREE,
+            ],
+            uint8_array_id: 4,
+            static_array_u8_id: 5,
+        },
+        // Object, ArrayBuffer, String, ArrayBufferView, Int8Array, Uint8Array, StaticArray<u8>
+        Rtti {
+            version: AbiVersion::V0_20,
+            exports: TRACING_EXPORTS,
+            flags: &[
+                0,
+                POINTERFREE,
+                POINTERFREE,
+                0,
+                INT8_ARRAY,
+                UINT8_ARRAY,
+                STATIC_ARRAY_U8 | POINTERFREE,
+            ],
+            uint8_array_id: 5,
+            static_array_u8_id: 6,
+        },
+    ];
+
+    /// Writes object header with class `id` at `ptr`.
+    fn write_object(mem: &Memory, ptr: usize, id: u32, fields: &[u32]) {
+        write_u32(mem, ptr - RT_ID_OFFSET, id).unwrap();
+        for (i, field) in fields.iter().enumerate() {
+            write_u32(mem, ptr + i * 4, *field).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rtti_tables() {
+        for rtti in RTTI {
+            let store = Store::default();
+            let mem = Memory::new(&store, MemoryType::new(Limits::new(1, None)));
+            write_rtti(&mem, rtti.version, 64, rtti.flags);
+            let module = exports(&store, rtti.exports, &[("__rtti_base", 64)]);
+            let layout = Layout::resolve(&module, &mem, Some(rtti.version))
+                .unwrap()
+                .unwrap();
+
+            write_object(&mem, 1024, rtti.uint8_array_id, &[2048, 2048, 3]);
+            assert_eq!(layout.view_data(&mem, 1024).unwrap(), Some((2048, 3)));
+            write_object(&mem, 1024, rtti.static_array_u8_id, &[]);
+            assert_eq!(layout.view_data(&mem, 1024).unwrap(), None);
+            write_object(&mem, 1024, layout.array_buffer_id, &[]);
+            assert_eq!(layout.view_data(&mem, 1024).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_view_data_without_rtti() {
+        let store = Store::default();
+        let mem = Memory::new(&store, MemoryType::new(Limits::new(1, None)));
+        write_object(&mem, 1024, 5, &[2048, 2048, 3]);
+
+        let module = exports(&store, TRACING_EXPORTS, &[]);
+        let layout = Layout::resolve(&module, &mem, None).unwrap().unwrap();
+        assert!(layout.view_data(&mem, 1024).is_err());
+
+        let module = exports(&store, TRACING_EXPORTS, &[("Uint8Array_ID", 5)]);
+        let layout = Layout::resolve(&module, &mem, None).unwrap().unwrap();
+        assert_eq!(layout.view_data(&mem, 1024).unwrap(), Some((2048, 3)));
+    }
+
+    #[test]
+    fn test_resolve_checks_class_ids() {
+        let store = Store::default();
+        let mem = Memory::new(&store, MemoryType::new(Limits::new(1, None)));
+        // Object, ArrayBuffer, String, ArrayBufferView
+        write_rtti(&mem, AbiVersion::V0_20, 64, &[0, 0, 0, ARRAYBUFFERVIEW]);
+
+        let module = exports(&store, TRACING_EXPORTS, &[("__rtti_base", 64)]);
+        assert_eq!(
+            resolve(&module, &mem, None).unwrap(),
+            Some(AbiVersion::V0_20)
+        );
+
+        let module = exports(
+            &store,
+            TRACING_EXPORTS,
+            &[("__rtti_base", 64), ("String_ID", 3)],
+        );
+        assert!(resolve(&module, &mem, None).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use super::{AbiVersion, Allocator, AsMem};
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyKey};
+use std::convert::TryFrom;
+use wasmtime::{Caller, Linker, Trap};
+
+fn signing_key(bytes: &[u8]) -> Result<SigningKey, Trap> {
+    SigningKey::new(bytes).map_err(|e| Trap::new(format!("invalid p256 key: {}", e)))
+}
+
+fn decode_signing_key(mem: &AsMem, ptr: i32) -> Result<SigningKey, Trap> {
+    mem.decode(ptr, signing_key)
+}
+
+/// Parses fixed size (r || s) or ASN.1 DER encoded signature, as produced by WebAuthn devices.
+fn parse_signature(bytes: &[u8]) -> Result<Signature, Trap> {
+    if bytes.len() == 64 {
+        Signature::try_from(bytes)
+    } else {
+        Signature::from_asn1(bytes)
+    }
+    .map_err(|e| Trap::new(format!("invalid p256 signature: {}", e)))
+}
+
+/// Traps on malformed public key or signature, returns `false` when signature does not match.
+fn verify(public: &[u8], message: &[u8], signature: &[u8]) -> Result<bool, Trap> {
+    let public =
+        VerifyKey::new(public).map_err(|e| Trap::new(format!("invalid p256 public key: {}", e)))?;
+    let signature = parse_signature(signature)?;
+    Ok(public.verify(message, &signature).is_ok())
+}
+
+/// Registers `p256.*` host functions for ECDSA over secp256r1 with SHA-256.
+///
+/// Malformed keys and signatures trap, `verify` returns `false` only for well-formed
+/// signatures that do not match.
+pub fn link_p256(module: &str, linker: &mut Linker, abi: Option<AbiVersion>) -> anyhow::Result<()> {
+    // returns 32 bytes secret key.
+    //export function newKey(): ArrayBuffer;
+    linker.func(
+        module,
+        "p256.newKey",
+        move |caller: Caller| -> Result<i32, Trap> {
+            let key = SigningKey::random(&mut rand::thread_rng());
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(key.to_bytes().as_ref())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    // returns 65 bytes uncompressed SEC1 public key.
+    //export function toPubKey(prvKey: ArrayBuffer): ArrayBuffer;
+    linker.func(
+        module,
+        "p256.toPubKey",
+        move |caller: Caller, prv_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let key = decode_signing_key(&mem, prv_ptr)?;
+            let public = VerifyKey::from(&key).to_encoded_point(false);
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(public.as_bytes())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    // message is hashed with SHA-256, returns 64 bytes signature (r || s).
+    //export function sign(prvKey: ArrayBuffer, message: ArrayBuffer): ArrayBuffer;
+    linker.func(
+        module,
+        "p256.sign",
+        move |caller: Caller, prv_ptr: i32, msg_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let key = decode_signing_key(&mem, prv_ptr)?;
+            let signature: Signature = mem.decode(msg_ptr, |message| Ok(key.sign(message)))?;
+            let mut allocator = Allocator::for_caller(&caller, abi)?;
+            let ptr = allocator.new_bytes(signature.as_ref())?;
+            allocator.retain(ptr)
+        },
+    )?;
+
+    // pubKey - compressed or uncompressed SEC1 public key,
+    // signature - 64 bytes (r || s) or ASN.1 DER.
+    //export function verify(pubKey: ArrayBuffer, message: ArrayBuffer, signature: ArrayBuffer): bool;
+    linker.func(
+        module,
+        "p256.verify",
+        move |caller: Caller, pub_ptr: i32, msg_ptr: i32, sig_ptr: i32| -> Result<i32, Trap> {
+            let mem = AsMem::for_caller(&caller, abi)?;
+            let valid = mem.decode(pub_ptr, |public| {
+                mem.decode(msg_ptr, |message| {
+                    mem.decode(sig_ptr, |signature| verify(public, message, signature))
+                })
+            })?;
+            Ok(valid as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // RFC 6979, appendix A.2.5
+    const SECRET: &str = "c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721";
+    const PUBLIC: &str = "0460fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6\
+                          7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+    /// Message, r and s of SHA-256 signatures.
+    const SIGNATURES: &[(&str, &str, &str)] = &[
+        (
+            "sample",
+            "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716",
+            "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8",
+        ),
+        (
+            "test",
+            "f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367",
+            "019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083",
+        ),
+    ];
+
+    #[test]
+    fn test_rfc6979() {
+        let key = signing_key(&hex::decode(SECRET).unwrap()).unwrap();
+        assert_eq!(
+            hex::encode(VerifyKey::from(&key).to_encoded_point(false).as_bytes()),
+            PUBLIC
+        );
+        let public = hex::decode(PUBLIC).unwrap();
+        for (message, r, s) in SIGNATURES {
+            let signature: Signature = key.sign(message.as_bytes());
+            assert_eq!(hex::encode(signature), format!("{}{}", r, s));
+            let signature = hex::decode(format!("{}{}", r, s)).unwrap();
+            assert!(verify(&public, message.as_bytes(), &signature).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_verify_malformed() {
+        let public = hex::decode(PUBLIC).unwrap();
+        let (message, r, s) = SIGNATURES[0];
+        // both integers have high bit set and need a leading zero in DER
+        let der = hex::decode(format!("3046022100{}022100{}", r, s)).unwrap();
+        assert!(verify(&public, message.as_bytes(), &der).unwrap());
+        assert!(!verify(&public, b"other message", &der).unwrap());
+
+        assert!(verify(&public[..64], message.as_bytes(), &der).is_err());
+        assert!(verify(&public, message.as_bytes(), &der[..40]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! HTTP gateway to a deployed application, for local development without yagna.
+
+use crate::deploy::{Deployment, EntryPoint};
+use crate::options::RuntimeOptions;
+use crate::runtime::ActivityInfo;
+use crate::service::{spawn_application, ApplicationChannel, Command};
+use actix_web::http::StatusCode;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use anyhow::{anyhow, bail};
+use futures::channel::{mpsc, oneshot};
+use futures::prelude::*;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+fn error(status: StatusCode, message: String) -> HttpResponse {
+    HttpResponse::build(status).json(json!({ "error": message }))
+}
+
+/// Converts request body to entry point args.
+///
+/// Args are passed either positionally as an array, or as an object keyed by arg names.
+fn args_from_json(ep: &EntryPoint, body: Value) -> anyhow::Result<Vec<String>> {
+    let to_arg = |value: Value| match value {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        value => Err(anyhow!("unsupported arg value: {}", value)),
+    };
+    match body {
+        Value::Null => Ok(Vec::new()),
+        Value::Array(values) => values.into_iter().map(to_arg).collect(),
+        Value::Object(mut values) => {
+            let args = ep
+                .args
+                .iter()
+                .enumerate()
+                .map(|(idx, arg_desc)| {
+                    let name = arg_desc
+                        .name()
+                        .ok_or_else(|| anyhow!("arg {} has no name, pass args as an array", idx))?;
+                    values
+                        .remove(name)
+                        .ok_or_else(|| anyhow!("missing arg: {}", name))
+                        .and_then(to_arg)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if let Some(name) = values.keys().next() {
+                bail!("unknown arg: {}", name);
+            }
+            Ok(args)
+        }
+        _ => bail!("expected array or object of args"),
+    }
+}
+
+#[actix_web::get("/entry-points")]
+async fn list_entry_points(deployment: web::Data<Deployment>) -> HttpResponse {
+    HttpResponse::Ok().json(deployment.entry_points())
+}
+
+#[actix_web::post("/run/{entryPoint}")]
+async fn run(
+    path: web::Path<(String,)>,
+    body: web::Json<Value>,
+    deployment: web::Data<Deployment>,
+    pids: web::Data<AtomicU32>,
+    app: web::Data<ApplicationChannel>,
+) -> HttpResponse {
+    let entry_point = path.into_inner().0;
+    let ep = match deployment.entry_point(&entry_point) {
+        Some(ep) => ep,
+        None => {
+            return error(
+                StatusCode::NOT_FOUND,
+                format!("unknown entrypoint: {}", entry_point),
+            )
+        }
+    };
+    let args = match args_from_json(ep, body.into_inner()).and_then(|args| {
+        ep.check_args(&args)?;
+        Ok(args)
+    }) {
+        Ok(args) => args,
+        Err(e) => return error(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+    };
+
+    let (status_tx, mut status_rx) = mpsc::unbounded();
+    let (outcome_tx, outcome_rx) = oneshot::channel();
+    let command = Command {
+        pid: pids.fetch_add(1, Ordering::Relaxed),
+        entry_point,
+        args,
+        status: status_tx,
+        outcome: Some(outcome_tx),
+    };
+    if app.send(command).is_err() {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "application is not running".to_string(),
+        );
+    }
+
+    let mut stdout = Vec::<u8>::new();
+    let mut stderr = Vec::<u8>::new();
+    while let Some(status) = status_rx.next().await {
+        stdout.extend(&status.stdout);
+        stderr.extend(&status.stderr);
+    }
+    let stdout = String::from_utf8_lossy(&stdout);
+    let stderr = String::from_utf8_lossy(&stderr);
+    match outcome_rx.await {
+        Ok(Ok(return_code)) => HttpResponse::Ok().json(json!({
+            "returnCode": return_code,
+            "stdout": stdout,
+            "stderr": stderr,
+        })),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(json!({
+            "error": e,
+            "stdout": stdout,
+            "stderr": stderr,
+        })),
+        Err(_) => error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "application crashed".to_string(),
+        ),
+    }
+}
+
+/// Serves application deployed in `work_dir`:
+///
+/// * `GET /entry-points` - entry points with their arg descriptions,
+/// * `POST /run/{entryPoint}` - runs entry point with args from JSON body.
+pub async fn serve(
+    work_dir: PathBuf,
+    activity: ActivityInfo,
+    options: RuntimeOptions,
+    bind: &str,
+) -> anyhow::Result<()> {
+    let deployment = web::Data::new(Deployment::load(&work_dir)?);
+    let pids = web::Data::new(AtomicU32::new(1));
+    let app = spawn_application(work_dir, activity, options);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(deployment.clone())
+            .app_data(pids.clone())
+            .data(app.clone())
+            .service(list_entry_points)
+            .service(run)
+    })
+    .bind(bind)?
+    .run()
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_named_args() {
+        let ep: EntryPoint = serde_json::from_value(json!({
+            "args": [
+                {"name": "voting_id", "type": "string"},
+                {"name": "count", "type": "i32"}
+            ]
+        }))
+        .unwrap();
+        let args = args_from_json(&ep, json!({"count": 3, "voting_id": "v1"})).unwrap();
+        assert_eq!(args, vec!["v1", "3"]);
+        assert!(ep.check_args(&args).is_ok());
+
+        assert!(args_from_json(&ep, json!({"voting_id": "v1"})).is_err());
+        assert!(args_from_json(&ep, json!({"count": 3, "voting_id": "v1", "x": 1})).is_err());
+        let args = args_from_json(&ep, json!(["v1", "three"])).unwrap();
+        assert!(ep.check_args(&args).is_err());
+    }
+}
@@ -1,13 +1,21 @@
-use crate::deploy::{Deployment, Output};
-use crate::runtime::{link_eth, link_io, AsMem};
+use crate::deploy::{Deployment, Output, INVOCATION_COUNTER_FILE};
+use crate::options::{RuntimeOptions, DEFAULT_GUARD_SIZE, DEFAULT_MAX_MEMORY};
+use crate::runtime::{
+    link_aead, link_context, link_ed25519, link_eth, link_io, link_keystore, link_logging,
+    link_p256, ActivityInfo, AsMem, ContextFactory, ContextHandle, FdState, Keystore, LogConfig,
+    VolumeUsage,
+};
 use futures::prelude::*;
 use futures::FutureExt;
 use rand::Rng;
+use serde::Serialize;
 use std::cell::RefCell;
-use std::convert::TryFrom;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
+use std::time::Duration;
 use wasmtime::{Caller, Linker, Module, Store, Trap};
 use ya_runtime_api::server::{
     self, AsyncResponse, ErrorResponse, KillProcess, ProcessStatus, RunProcess, RunProcessResp,
@@ -28,6 +36,37 @@ impl OutputHandler for NoopOutputHandler {
     fn handle_stderr(&self, _message: &[u8]) {}
 }
 
+/// Writes guest output to the process stdout and stderr.
+struct StdOutputHandler;
+
+impl StdOutputHandler {
+    fn write_line(mut out: impl Write, message: &[u8]) {
+        let _ = out.write_all(message);
+        if !message.ends_with(b"\n") {
+            let _ = out.write_all(b"\n");
+        }
+    }
+}
+
+impl OutputHandler for StdOutputHandler {
+    fn handle_stdout(&self, message: &[u8]) {
+        Self::write_line(std::io::stdout(), message)
+    }
+
+    fn handle_stderr(&self, message: &[u8]) {
+        Self::write_line(std::io::stderr(), message)
+    }
+}
+
+/// Module exporting an empty `noop` function.
+const NOOP_MODULE: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic, version
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+    0x03, 0x02, 0x01, 0x00, // function section: type 0
+    0x07, 0x08, 0x01, 0x04, b'n', b'o', b'o', b'p', 0x00, 0x00, // export section: "noop"
+    0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section: empty body
+];
+
 thread_local! {
     static OUTPUT_HADLER: RefCell<Box<dyn OutputHandler>> = RefCell::new(Box::new(NoopOutputHandler));
 }
@@ -36,16 +75,30 @@ pub struct Application {
     store: Store,
     app: wasmtime::Instance,
     deployment: Deployment,
+    io: FdState,
+    context: ContextHandle,
+    contexts: RefCell<ContextFactory>,
+    timeout: Option<Duration>,
 }
 
 impl Application {
-    fn new(work_dir: &Path) -> anyhow::Result<Self> {
+    fn new(
+        work_dir: &Path,
+        activity: ActivityInfo,
+        options: &RuntimeOptions,
+    ) -> anyhow::Result<Self> {
         let deployment = Deployment::load(work_dir)?;
         let mut config = wasmtime::Config::new();
-        config.static_memory_guard_size(0x1_0000);
+        config.static_memory_guard_size(options.guard_size.unwrap_or(DEFAULT_GUARD_SIZE));
         config.interruptable(true);
-        // max: 50M
-        config.static_memory_maximum_size(50 * 2 << 20);
+        config.static_memory_maximum_size(options.max_memory.unwrap_or(DEFAULT_MAX_MEMORY));
+        if let Some(optimize) = options.optimize {
+            config.cranelift_opt_level(if optimize {
+                wasmtime::OptLevel::Speed
+            } else {
+                wasmtime::OptLevel::None
+            });
+        }
 
         let engine = wasmtime::Engine::new(&config);
         let store = Store::new(&engine);
@@ -54,8 +107,36 @@ impl Application {
             .get_image()?
             .get_bytes(&deployment.main_entry().wasm_path)?;
         let module = Module::new(&engine, wasm_binary)?;
-        link_io("ya", &mut linker, work_dir.to_owned(), deployment.vols())?;
-        link_eth("ya", &mut linker)?;
+        let abi = deployment.main_entry().abi;
+        let io = link_io(
+            "ya",
+            &mut linker,
+            abi,
+            work_dir.to_owned(),
+            deployment.vols(),
+            options.io_limits(),
+        )?;
+        link_eth("ya", &mut linker, abi)?;
+        link_ed25519("ya", &mut linker, abi)?;
+        link_p256("ya", &mut linker, abi)?;
+        link_aead("ya", &mut linker, abi)?;
+        let keystore = match deployment.keystore_dir(work_dir) {
+            Some(dir) => Keystore::from_env(&dir)
+                .map_err(|e| log::warn!("keystore disabled: {:#}", e))
+                .ok(),
+            None => None,
+        };
+        link_keystore("ya", &mut linker, abi, keystore)?;
+        let context = ContextHandle::default();
+        link_context("ya", &mut linker, abi, context.clone())?;
+        let contexts = ContextFactory::new(activity, Some(work_dir.join(INVOCATION_COUNTER_FILE)));
+        let log_config = LogConfig::from_env(deployment.name()).unwrap_or_else(|e| {
+            log::warn!("{:#}", e);
+            LogConfig::new(deployment.name())
+        });
+        link_logging("ya", &mut linker, abi, log_config, |message| {
+            OUTPUT_HADLER.with(|h| h.borrow().handle_stderr(message))
+        })?;
 
         linker.func(
             "env",
@@ -66,7 +147,7 @@ impl Application {
                   line: i32,
                   _column: i32|
                   -> Result<(), Trap> {
-                let mem = AsMem::try_from(caller)?;
+                let mem = AsMem::for_caller(&caller, abi)?;
                 let message = mem.decode_str(message_ptr)?;
                 OUTPUT_HADLER.with(|h| {
                     let out_message =
@@ -81,12 +162,11 @@ impl Application {
         linker.func("env", "seed", |_: Caller| -> Result<f64, Trap> {
             Ok(rand::thread_rng().gen())
         })?;
-        //linker.define("ya", "context", Extern)
         linker.func(
             "ya",
             "log",
             move |caller: Caller, message: i32| -> Result<(), Trap> {
-                let mem = AsMem::try_from(caller)?;
+                let mem = AsMem::for_caller(&caller, abi)?;
                 let msg = mem.decode_str(message)?;
                 OUTPUT_HADLER.with(|h| h.borrow().handle_stderr(msg.as_ref()));
                 Ok::<_, Trap>(())
@@ -98,10 +178,59 @@ impl Application {
             store,
             app,
             deployment,
+            io,
+            context,
+            contexts: RefCell::new(contexts),
+            timeout: options.timeout,
         })
     }
 
-    fn run(&self, entry_point: &str, args: Vec<String>) -> anyhow::Result<i32> {
+    /// Calls `func`, interrupting it when it does not finish in `timeout`.
+    fn call_with_timeout(
+        &self,
+        func: &wasmtime::Func,
+        args: &[wasmtime::Val],
+        timeout: Duration,
+    ) -> anyhow::Result<Box<[wasmtime::Val]>> {
+        let interrupt_handle = self.store.interrupt_handle()?;
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        let timer = std::thread::spawn(move || match done_rx.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                interrupt_handle.interrupt();
+                true
+            }
+            _ => false,
+        });
+        let result = func.call(args);
+        drop(done_tx);
+        let timed_out = timer.join().unwrap_or(false);
+        if timed_out && result.is_ok() {
+            self.consume_interrupt()?;
+        }
+        match result {
+            Err(_) if timed_out => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("execution timed out after {:?}", timeout),
+            )
+            .into()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Consumes an interrupt which came after the call returned.
+    ///
+    /// The interrupt stays pending on the store and would stop the next call right away,
+    /// entering wasm once clears it.
+    fn consume_interrupt(&self) -> anyhow::Result<()> {
+        let module = Module::new(self.store.engine(), NOOP_MODULE)?;
+        let instance = wasmtime::Instance::new(&self.store, &module, &[])?;
+        if let Some(noop) = instance.get_func("noop") {
+            let _ = noop.call(&[]);
+        }
+        Ok(())
+    }
+
+    fn run(&self, pid: u64, entry_point: &str, args: Vec<String>) -> anyhow::Result<i32> {
         let ep = match self.deployment.entry_point(entry_point) {
             Some(v) => v,
             None => anyhow::bail!("unknown entrypoint: {}", entry_point),
@@ -110,8 +239,17 @@ impl Application {
             Some(v) => v,
             None => anyhow::bail!("entrypoint {} not exported", entry_point),
         };
-        let result = func.call(&ep.convert_args(&self.app, args)?)?;
-        let mem = AsMem::for_instance(&self.app)?;
+        ep.check_args(&args)?;
+        let _context = self
+            .context
+            .enter(self.contexts.borrow_mut().next(pid, entry_point));
+        let abi = self.deployment.main_entry().abi;
+        let args = ep.convert_args(&self.app, abi, args)?;
+        let result = match self.timeout {
+            Some(timeout) => self.call_with_timeout(&func, &args, timeout)?,
+            None => func.call(&args)?,
+        };
+        let mem = AsMem::for_instance(&self.app, abi)?;
         match &ep.output {
             Output::ExitCode => {
                 let err_code = wasmtime::Val::i32(&result[0])
@@ -144,6 +282,8 @@ pub struct Command {
     pub entry_point: String,
     pub args: Vec<String>,
     pub status: futures::channel::mpsc::UnboundedSender<ProcessStatus>,
+    /// Receives return code, or the reason the invocation failed.
+    pub outcome: Option<futures::channel::oneshot::Sender<Result<i32, String>>>,
 }
 
 struct SenderHandler {
@@ -171,32 +311,81 @@ impl OutputHandler for SenderHandler {
     }
 }
 
+fn with_output_handler<T, F: FnOnce() -> T>(handler: Box<dyn OutputHandler>, f: F) -> T {
+    let prev_handler = OUTPUT_HADLER.with(|h| h.replace(handler));
+    let ret = f();
+    let _ = OUTPUT_HADLER.with(|h| h.replace(prev_handler));
+    ret
+}
+
 pub fn with_sender<T, F: FnOnce() -> T>(
     pid: u64,
     sender: futures::channel::mpsc::UnboundedSender<ProcessStatus>,
     f: F,
 ) -> T {
-    let prev_handler = OUTPUT_HADLER.with(|h| h.replace(Box::new(SenderHandler { pid, sender })));
-    let ret = f();
-    let _ = OUTPUT_HADLER.with(|h| h.replace(prev_handler));
-    ret
+    with_output_handler(Box::new(SenderHandler { pid, sender }), f)
+}
+
+/// Runs single entry point of the deployed application, without the runtime service.
+///
+/// Output goes to the process stdout and stderr.
+pub fn run(
+    work_dir: &Path,
+    activity: ActivityInfo,
+    options: RuntimeOptions,
+    entry_point: &str,
+    args: Vec<String>,
+) -> anyhow::Result<RunOutcome> {
+    let app = Application::new(work_dir, activity, &options)?;
+    let return_code =
+        with_output_handler(Box::new(StdOutputHandler), || app.run(1, entry_point, args))?;
+    Ok(RunOutcome {
+        return_code,
+        volumes: app.io.volume_usage()?,
+    })
+}
+
+/// Result of [`run`].
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RunOutcome {
+    /// Entry point return code.
+    #[serde(skip)]
+    pub return_code: i32,
+    /// Disk usage of the application volumes.
+    pub volumes: Vec<VolumeUsage>,
 }
 
 pub type ApplicationChannel = std::sync::mpsc::Sender<Command>;
 
-pub fn spawn_application(work_dir: PathBuf) -> ApplicationChannel {
+pub fn spawn_application(
+    work_dir: PathBuf,
+    activity: ActivityInfo,
+    options: RuntimeOptions,
+) -> ApplicationChannel {
     let (tx, rx) = std::sync::mpsc::channel::<Command>();
     let handle = tokio::task::spawn_blocking(move || {
-        let app = Application::new(&work_dir)?;
+        let app = Application::new(&work_dir, activity, &options)?;
         log::info!("started");
         for command in rx.iter() {
-            let status = command.status.clone();
-            let pid = command.pid as u64;
+            let Command {
+                pid,
+                entry_point,
+                args,
+                status,
+                outcome,
+            } = command;
+            let pid = pid as u64;
 
-            log::debug!("command pid:{}, ep:{}", pid, &command.entry_point);
-            match with_sender(pid, command.status.clone(), || {
-                app.run(&command.entry_point, command.args)
-            }) {
+            log::debug!("command pid:{}, ep:{}", pid, &entry_point);
+            let result = with_sender(pid, status.clone(), || app.run(pid, &entry_point, args));
+            if let Some(outcome) = outcome {
+                let _ignore = outcome.send(match &result {
+                    Ok(return_code) => Ok(*return_code),
+                    Err(e) => Err(e.to_string()),
+                });
+            }
+            match result {
                 Ok(return_code) => {
                     let _ignore = status.unbounded_send(ProcessStatus {
                         pid,
@@ -236,10 +425,15 @@ pub struct Service<T: RuntimeEvent> {
 }
 
 impl<T: RuntimeEvent> Service<T> {
-    pub fn new(events: T, work_dir: PathBuf) -> Self {
+    pub fn new(
+        events: T,
+        work_dir: PathBuf,
+        activity: ActivityInfo,
+        options: RuntimeOptions,
+    ) -> Self {
         let events = Arc::new(events);
         let pid = AtomicI32::new(1);
-        let application = spawn_application(work_dir);
+        let application = spawn_application(work_dir, activity, options);
         Self {
             events,
             pid,
@@ -264,6 +458,7 @@ impl<T: RuntimeEvent + 'static> RuntimeService for Service<T> {
             entry_point: run.bin,
             args: run.args,
             status: tx,
+            outcome: None,
         }) {
             return future::err(ErrorResponse {
                 code: 0,
@@ -295,7 +490,71 @@ impl<T: RuntimeEvent + 'static> RuntimeService for Service<T> {
     }
 }
 
-pub async fn start(workdir: &Path) -> anyhow::Result<()> {
-    server::run(|emiter| Service::new(emiter, workdir.to_path_buf())).await;
+pub async fn start(
+    workdir: &Path,
+    activity: ActivityInfo,
+    options: RuntimeOptions,
+) -> anyhow::Result<()> {
+    server::run(|emiter| {
+        Service::new(
+            emiter,
+            workdir.to_path_buf(),
+            activity.clone(),
+            options.clone(),
+        )
+    })
+    .await;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deploy::MANIFEST_FILE;
+
+    fn application(dir: &Path, wat: &str) -> Application {
+        let image = dir.join("app.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&image).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file(MANIFEST_FILE, options).unwrap();
+        zip.write_all(
+            br#"{"id": "app", "name": "app", "runtime": "aswasm",
+                "main": {"wasm-path": "app.wasm"}}"#,
+        )
+        .unwrap();
+        zip.start_file("app.wasm", options).unwrap();
+        zip.write_all(&wat::parse_str(wat).unwrap()).unwrap();
+        zip.finish().unwrap();
+
+        let work_dir = dir.join("work");
+        std::fs::create_dir(&work_dir).unwrap();
+        crate::deploy(&work_dir, &image).unwrap();
+        Application::new(
+            &work_dir,
+            ActivityInfo::default(),
+            &RuntimeOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_call_after_late_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = application(
+            dir.path(),
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "spin") (param i32)
+                    (loop
+                        (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))
+                        (br_if 0 (i32.gt_s (i32.const 0))))))"#,
+        );
+        let spin = app.app.get_func("spin").unwrap();
+        for i in 0..5000 {
+            // spins for about the timeout, so that some calls return just before the timer fires
+            let _ = app.call_with_timeout(&spin, &[(i * 40).into()], Duration::from_micros(100));
+            app.call_with_timeout(&spin, &[0.into()], Duration::from_secs(10))
+                .unwrap();
+        }
+    }
+}
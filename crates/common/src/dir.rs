@@ -0,0 +1,43 @@
+use std::io;
+use std::path::Path;
+
+/// Total size of files in the directory, symbolic links are not followed.
+///
+/// Entries removed during the walk are skipped.
+pub fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut entries = std::fs::read_dir(dir)?.collect::<Vec<_>>();
+    while let Some(entry) = entries.pop() {
+        let entry = entry?;
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if meta.is_dir() {
+            match std::fs::read_dir(entry.path()) {
+                Ok(dir) => entries.extend(dir),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        } else if meta.is_file() {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dir_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b/c")).unwrap();
+        std::fs::write(dir.path().join("x"), b"abcd").unwrap();
+        std::fs::write(dir.path().join("a/b/c/y"), b"ef").unwrap();
+        assert_eq!(dir_size(dir.path()).unwrap(), 6);
+        assert!(dir_size(&dir.path().join("missing")).is_err());
+    }
+}
@@ -0,0 +1,7 @@
+//! Helpers shared by the WASI and aswasm runtimes and their command line tool.
+
+mod dir;
+mod size;
+
+pub use dir::dir_size;
+pub use size::{parse_size, Size};
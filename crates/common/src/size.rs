@@ -0,0 +1,67 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Parses size spec, in bytes or with `k`, `m` or `g` suffix (eg. `250m`, `1.5g`).
+///
+/// Negative sizes and sizes that do not fit in `u64` are refused.
+pub fn parse_size(spec: &str) -> Result<u64> {
+    let spec = spec.trim();
+    let (value, scale) = match spec.as_bytes().last() {
+        Some(b'k') | Some(b'K') => (&spec[..spec.len() - 1], 0x400u64),
+        Some(b'm') | Some(b'M') => (&spec[..spec.len() - 1], 0x100_000),
+        Some(b'g') | Some(b'G') => (&spec[..spec.len() - 1], 0x40_000_000),
+        _ => (spec, 1),
+    };
+    let bytes = match value.parse::<u64>() {
+        Ok(value) => value.checked_mul(scale),
+        Err(_) => match value.parse::<f64>() {
+            Ok(value) if value >= 0.0 && value.is_finite() => {
+                let bytes = value * scale as f64;
+                // `u64::MAX as f64` rounds up to 2^64
+                if bytes < u64::MAX as f64 {
+                    Some(bytes as u64)
+                } else {
+                    None
+                }
+            }
+            _ => bail!("invalid size spec: {}", spec),
+        },
+    };
+    bytes.ok_or_else(|| anyhow!("size out of range: {}", spec))
+}
+
+/// Size in bytes, or a spec with `k`, `m` or `g` suffix.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Size {
+    Bytes(u64),
+    Spec(String),
+}
+
+impl Size {
+    pub fn bytes(&self) -> Result<u64> {
+        match self {
+            Size::Bytes(bytes) => Ok(*bytes),
+            Size::Spec(spec) => parse_size(spec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size(" 64k ").unwrap(), 64 * 0x400);
+        assert_eq!(parse_size("1.5g").unwrap(), 3 * 0x20_000_000);
+        assert_eq!(parse_size("16777215G").unwrap(), 0xff_ffff * 0x40_000_000);
+        assert!(parse_size("m").is_err());
+        assert!(parse_size("-1m").is_err());
+        assert!(parse_size("nan").is_err());
+        assert!(parse_size("17179869184g").is_err());
+        assert!(parse_size("18446744073709551615k").is_err());
+        assert!(parse_size("1e30").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Runtime configuration file.
+//!
+//! Read from `--config <file>`, or from `ya-runtime-wasi.toml` next to the runtime
+//! binary (where the plugin descriptor is installed) when it exists. Precedence,
+//! highest first:
+//!
+//! 1. command line flags (`--debug`),
+//! 2. environment variables (`YA_WASI_LOG`, `YA_RUNTIME_WASI_*`, `YA_RUNTIME_ASWASM_*`),
+//! 3. configuration file,
+//! 4. built-in defaults.
+
+#[cfg(any(feature = "wasi", feature = "aswasm"))]
+use anyhow::bail;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "wasi", feature = "aswasm"))]
+use std::time::Duration;
+use ya_runtime_common::Size;
+
+pub const CONFIG_FILE: &str = "ya-runtime-wasi.toml";
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Default log filter, in `YA_WASI_LOG` format.
+    pub log_level: Option<String>,
+    #[serde(default)]
+    pub wasi: EngineConfig,
+    #[serde(default)]
+    pub aswasm: EngineConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Maximum memory size, in bytes or with `k`, `m` or `g` suffix.
+    pub max_memory: Option<Size>,
+    pub optimize: Option<bool>,
+    /// Graphene-SGX profile, WASI only.
+    pub sgx: Option<bool>,
+    /// Maximum execution time of a single run.
+    pub timeout_secs: Option<u64>,
+    /// Compiled modules cache, WASI only.
+    pub cache_dir: Option<PathBuf>,
+    /// Guard region after linear memory, aswasm only.
+    pub guard_size: Option<Size>,
+    /// Maximum number of files open at the same time, aswasm only.
+    pub max_fds: Option<usize>,
+    /// Maximum size of a file written by the guest, aswasm only.
+    pub max_file_size: Option<Size>,
+    /// Maximum size of files in a volume, caps manifest quotas.
+    pub max_volume_size: Option<Size>,
+    /// `host:port` endpoints guests may connect to, WASI only.
+    pub network_allow: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Loads config from `path`, or from the default location when not given.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match Self::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("parsing config {}", path.display()))
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        Some(std::env::current_exe().ok()?.with_file_name(CONFIG_FILE))
+    }
+
+    /// WASI options from the file, overridden by environment.
+    #[cfg(feature = "wasi")]
+    pub fn wasi_options(&self) -> Result<ya_runtime_wasi::RuntimeOptions> {
+        let config = &self.wasi;
+        if config.guard_size.is_some() || config.max_fds.is_some() || config.max_file_size.is_some()
+        {
+            bail!("guard-size, max-fds and max-file-size are not supported by wasi");
+        }
+        let mut options = ya_runtime_wasi::RuntimeOptions::default();
+        if let Some(max_memory) = &config.max_memory {
+            options = options.with_static_memory(max_memory.bytes()?);
+        }
+        if let Some(optimize) = config.optimize {
+            options = options.with_optimize(optimize);
+        }
+        if let Some(sgx) = config.sgx {
+            options = options.with_sgx_profile(sgx);
+        }
+        options = options
+            .with_timeout(config.timeout_secs.map(Duration::from_secs))
+            .with_cache_dir(config.cache_dir.clone());
+        if let Some(max_volume_size) = &config.max_volume_size {
+            options = options.with_max_volume_size(max_volume_size.bytes()?);
+        }
+        if let Some(network_allow) = &config.network_allow {
+            options = options.with_network_allowlist(network_allow.clone());
+        }
+        Ok(options.with_env()?)
+    }
+
+    /// aswasm options from the file, overridden by environment.
+    #[cfg(feature = "aswasm")]
+    pub fn aswasm_options(&self) -> Result<ya_runtime_aswasm::RuntimeOptions> {
+        let config = &self.aswasm;
+        if config.sgx.is_some() || config.cache_dir.is_some() || config.network_allow.is_some() {
+            bail!("sgx, cache-dir and network-allow are not supported by aswasm");
+        }
+        let mut options = ya_runtime_aswasm::RuntimeOptions::default();
+        if let Some(max_memory) = &config.max_memory {
+            options = options.with_max_memory(max_memory.bytes()?);
+        }
+        if let Some(guard_size) = &config.guard_size {
+            options = options.with_guard_size(guard_size.bytes()?);
+        }
+        if let Some(max_file_size) = &config.max_file_size {
+            options = options.with_max_file_size(max_file_size.bytes()?);
+        }
+        if let Some(max_volume_size) = &config.max_volume_size {
+            options = options.with_max_volume_size(max_volume_size.bytes()?);
+        }
+        if let Some(optimize) = config.optimize {
+            options = options.with_optimize(optimize);
+        }
+        options
+            .with_max_fds(config.max_fds)
+            .with_timeout(config.timeout_secs.map(Duration::from_secs))
+            .with_env()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config: Config = toml::from_str(
+            r#"
+            log-level = "info"
+
+            [wasi]
+            max-memory = "1.5g"
+            timeout-secs = 60
+            max-volume-size = "2g"
+            network-allow = ["127.0.0.1:8080"]
+
+            [aswasm]
+            max-memory = 4096
+            max-fds = 64
+            max-file-size = "10m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log_level.as_deref(), Some("info"));
+        let max_memory = config.wasi.max_memory.as_ref().unwrap();
+        assert_eq!(max_memory.bytes().unwrap(), 3 * 0x20_000_000);
+        assert_eq!(config.wasi.timeout_secs, Some(60));
+        let max_volume_size = config.wasi.max_volume_size.as_ref().unwrap();
+        assert_eq!(max_volume_size.bytes().unwrap(), 2 * 0x40_000_000);
+        assert_eq!(
+            config.wasi.network_allow,
+            Some(vec!["127.0.0.1:8080".to_string()])
+        );
+        let max_memory = config.aswasm.max_memory.as_ref().unwrap();
+        assert_eq!(max_memory.bytes().unwrap(), 4096);
+        assert_eq!(config.aswasm.max_fds, Some(64));
+        let max_file_size = config.aswasm.max_file_size.as_ref().unwrap();
+        assert_eq!(max_file_size.bytes().unwrap(), 10 * 0x100_000);
+        assert!(toml::from_str::<Config>("[wasi]\nmax-mem = 1").is_err());
+        assert!(toml::from_str::<Config>("[aswasm]\nfuel = 1").is_err());
+    }
+}
@@ -1,3 +1,6 @@
+mod config;
+mod output;
+
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
@@ -5,6 +8,9 @@ use serde::Deserialize;
 use serde_json;
 use std::fs::OpenOptions;
 use structopt::StructOpt;
+
+use config::Config;
+use output::{Outcome, OutputFormat, Report};
 #[cfg(feature = "aswasm")]
 use ya_runtime_aswasm as aswasm;
 #[cfg(feature = "wasi")]
@@ -44,6 +50,13 @@ macro_rules! with_aswasm {
     }};
 }
 
+#[cfg(feature = "server")]
+macro_rules! with_server {
+    ($s:expr) => {{
+        $s
+    }};
+}
+
 #[cfg(not(feature = "wasi"))]
 macro_rules! with_wasi {
     ($s:expr) => {
@@ -60,37 +73,74 @@ macro_rules! with_aswasm {
 
 #[derive(StructOpt)]
 enum Commands {
-    Deploy {},
+    /// Deploys the package, replacing previous deployment of the workdir.
+    Deploy {
+        /// Reuses volumes of the previous deployment instead of creating empty ones.
+        #[structopt(long)]
+        keep_volumes: bool,
+    },
+    /// Removes volumes and deployment files from the workdir.
+    Undeploy {},
     Start {},
     Run {
         #[structopt(short = "e", long = "entrypoint")]
         entrypoint: String,
         args: Vec<String>,
     },
+    /// Serves aswasm app over HTTP, deploys it first when needed.
+    Serve {
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        bind: String,
+    },
     Test {},
 }
 
+#[cfg(not(feature = "server"))]
+macro_rules! with_server {
+    ($s:expr) => {
+        unimplemented!()
+    };
+}
+
 #[derive(StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 struct CmdArgs {
     #[structopt(short, long, required_ifs(
         &[
             ("command", "deploy"),
+            ("command", "undeploy"),
             ("command", "start"),
-            ("command", "run")
+            ("command", "run"),
+            ("command", "serve")
         ])
     )]
     workdir: Option<PathBuf>,
     #[structopt(short, long, required_ifs(
         &[
             ("command", "deploy"),
+            ("command", "undeploy"),
             ("command", "start"),
-            ("command", "run")
+            ("command", "run"),
+            ("command", "serve")
         ])
     )]
     task_package: Option<PathBuf>,
     #[structopt(long)]
     debug: bool,
+    /// Configuration file, see `ya-runtime-wasi.toml`.
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    /// Output format, `text` or `json`.
+    #[structopt(long, default_value = "text")]
+    output: OutputFormat,
+    /// Activity the runtime is started for, exposed to aswasm guests in `ya.context()`.
+    #[structopt(long)]
+    #[cfg_attr(not(feature = "aswasm"), allow(dead_code))]
+    activity_id: Option<String>,
+    /// Agreement the runtime is started for, exposed to aswasm guests in `ya.context()`.
+    #[structopt(long)]
+    #[cfg_attr(not(feature = "aswasm"), allow(dead_code))]
+    agreement_id: Option<String>,
     #[structopt(subcommand)]
     command: Commands,
 }
@@ -100,6 +150,14 @@ impl CmdArgs {
         self.workdir.clone().context("No workdir arg")
     }
 
+    #[cfg(feature = "aswasm")]
+    fn activity(&self) -> aswasm::ActivityInfo {
+        aswasm::ActivityInfo {
+            activity_id: self.activity_id.clone(),
+            agreement_id: self.agreement_id.clone(),
+        }
+    }
+
     fn task_package(&self) -> anyhow::Result<PathBuf> {
         self.task_package.clone().context("No task_package arg")
     }
@@ -108,22 +166,47 @@ impl CmdArgs {
 fn main() -> Result<()> {
     let cmdline = CmdArgs::from_args();
 
+    match cmdline.output {
+        OutputFormat::Text => {
+            let outcome = execute(&cmdline)?;
+            if let Some(result) = outcome.result {
+                println!("{}\n", serde_json::to_string(&result)?);
+            }
+            if outcome.exit_code != 0 {
+                std::process::exit(outcome.exit_code);
+            }
+            Ok(())
+        }
+        OutputFormat::Json => {
+            let report = Report::from(execute(&cmdline));
+            println!("{}", serde_json::to_string(&report)?);
+            std::process::exit(report.exit_code)
+        }
+    }
+}
+
+fn execute(cmdline: &CmdArgs) -> Result<Outcome> {
     if let Commands::Test {} = cmdline.command {
-        return Ok(());
+        return Ok(Outcome::default());
     }
 
-    env_logger::Builder::from_env("YA_WASI_LOG")
-        .filter(Some("cranelift_codegen"), log::LevelFilter::Error)
-        .filter(Some("cranelift_wasm"), log::LevelFilter::Error)
-        .filter(
-            Some("wasi_common"),
-            if cmdline.debug {
-                log::LevelFilter::Info
-            } else {
-                log::LevelFilter::Error
-            },
-        )
-        .init();
+    let config = Config::load(cmdline.config.as_deref())?;
+
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or(
+        "YA_WASI_LOG",
+        config.log_level.as_deref().unwrap_or("error"),
+    ))
+    .filter(Some("cranelift_codegen"), log::LevelFilter::Error)
+    .filter(Some("cranelift_wasm"), log::LevelFilter::Error)
+    .filter(
+        Some("wasi_common"),
+        if cmdline.debug {
+            log::LevelFilter::Info
+        } else {
+            log::LevelFilter::Error
+        },
+    )
+    .init();
 
     let runtime = detect_runtime(&cmdline.task_package()?)?;
 
@@ -133,33 +216,85 @@ fn main() -> Result<()> {
             ref entrypoint,
             ref args,
         } => match runtime {
-            RuntimeType::WASI => with_wasi!(wasi::RuntimeOptions::from_env()?.run(
-                cmdline.workdir()?,
-                entrypoint,
-                args.clone()
-            )),
-            RuntimeType::ASWASM => {
-                anyhow::bail!("aswasm is blocking engine, run op is not supported.")
-            }
+            RuntimeType::WASI => with_wasi!({
+                let outcome =
+                    config
+                        .wasi_options()?
+                        .run(cmdline.workdir()?, entrypoint, args.clone())?;
+                Ok(Outcome {
+                    result: Some(serde_json::to_value(&outcome)?),
+                    ..Outcome::default()
+                })
+            }),
+            RuntimeType::ASWASM => with_aswasm!({
+                let outcome = config.aswasm_options()?.run(
+                    &cmdline.workdir()?,
+                    cmdline.activity(),
+                    entrypoint,
+                    args.clone(),
+                )?;
+                Ok(Outcome {
+                    exit_code: outcome.return_code,
+                    result: Some(serde_json::to_value(&outcome)?),
+                })
+            }),
         },
-        Commands::Deploy {} => {
+        #[allow(unused_variables)]
+        Commands::Deploy { keep_volumes } => {
             let res = match runtime {
+                RuntimeType::WASI => with_wasi!(wasi::DeployOptions::default()
+                    .with_keep_volumes(keep_volumes)
+                    .deploy(&cmdline.workdir()?, cmdline.task_package()?)
+                    .map_err(anyhow::Error::from)),
+                RuntimeType::ASWASM => with_aswasm!(aswasm::DeployOptions::default()
+                    .with_keep_volumes(keep_volumes)
+                    .deploy(&cmdline.workdir()?, &cmdline.task_package()?)),
+            }?;
+            Ok(Outcome {
+                result: Some(serde_json::to_value(&res)?),
+                ..Outcome::default()
+            })
+        }
+        Commands::Undeploy {} => {
+            match runtime {
+                RuntimeType::WASI => {
+                    with_wasi!(wasi::undeploy(&cmdline.workdir()?).map_err(anyhow::Error::from))
+                }
+                RuntimeType::ASWASM => with_aswasm!(aswasm::undeploy(&cmdline.workdir()?)),
+            }?;
+            Ok(Outcome::default())
+        }
+        Commands::Start {} => {
+            match runtime {
                 RuntimeType::WASI => {
-                    with_wasi!(wasi::deploy(&cmdline.workdir()?, cmdline.task_package()?))
+                    with_wasi!(config
+                        .wasi_options()?
+                        .start(cmdline.workdir()?)
+                        .map_err(anyhow::Error::from))
                 }
                 RuntimeType::ASWASM => {
-                    with_aswasm!(aswasm::deploy(&cmdline.workdir, cmdline.task_package()?))
+                    with_aswasm!(config
+                        .aswasm_options()?
+                        .start(&cmdline.workdir()?, cmdline.activity()))
                 }
             }?;
-            println!("{}\n", serde_json::to_string(&res)?);
-            Ok(())
+            Ok(Outcome::default())
         }
-        Commands::Start {} => match runtime {
-            RuntimeType::WASI => {
-                with_wasi!(wasi::RuntimeOptions::from_env()?.start(cmdline.workdir()?))
-            }
-            RuntimeType::ASWASM => with_aswasm!(aswasm::start(cmdline.workdir()?)),
+        #[allow(unused_variables)]
+        Commands::Serve { ref bind } => match runtime {
+            RuntimeType::WASI => anyhow::bail!("serve is only supported for aswasm apps"),
+            RuntimeType::ASWASM => with_server!({
+                let work_dir = cmdline.workdir()?;
+                if !work_dir.join("deploy.json").exists() {
+                    std::fs::create_dir_all(&work_dir)?;
+                    aswasm::deploy(&work_dir, &cmdline.task_package()?)?;
+                }
+                config
+                    .aswasm_options()?
+                    .serve(&work_dir, cmdline.activity(), bind)?;
+                Ok(Outcome::default())
+            }),
         },
-        Commands::Test {} => Ok(()),
+        Commands::Test {} => Ok(Outcome::default()),
     }
 }
@@ -0,0 +1,214 @@
+//! Machine readable command output, selected with `--output json`.
+//!
+//! Each command prints exactly one JSON object as the last line of stdout:
+//!
+//! ```json
+//! {"status": "ok", "exitCode": 0, "result": {...}}
+//! {"status": "error", "exitCode": 1, "error": {"kind": "trap", "message": "...", "chain": ["..."]}}
+//! ```
+//!
+//! `result` is present only for commands producing one: the deployment of `deploy`
+//! and the outcome of `run`, such as volume usage. Guest output of `run`
+//! is written to stdout before the object.
+
+use serde::Serialize;
+use std::str::FromStr;
+use wasmtime::Trap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => anyhow::bail!("invalid output format: {}, expected text or json", s),
+        }
+    }
+}
+
+/// Result of a successfully executed command.
+#[derive(Default)]
+pub struct Outcome {
+    pub result: Option<serde_json::Value>,
+    pub exit_code: i32,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    /// Invalid package or manifest.
+    Manifest,
+    Io,
+    /// Wasm module failed to compile or link.
+    Compile,
+    Trap,
+    Timeout,
+    Limit,
+    Other,
+}
+
+impl ErrorKind {
+    /// Kind of the innermost recognized error in the chain, limits are not refined by their
+    /// causes.
+    pub fn of(err: &anyhow::Error) -> Self {
+        let kinds = err.chain().filter_map(|cause| {
+            #[cfg(feature = "wasi")]
+            {
+                if let Some(e) = cause.downcast_ref::<ya_runtime_wasi::Error>() {
+                    return Self::of_wasi(e);
+                }
+            }
+            if cause.is::<Trap>() {
+                Some(ErrorKind::Trap)
+            } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                Some(Self::of_io(e))
+            } else if cause.is::<wasmtime_jit::SetupError>() {
+                Some(ErrorKind::Compile)
+            } else if cause.is::<serde_json::Error>() || cause.is::<zip::result::ZipError>() {
+                Some(ErrorKind::Manifest)
+            } else {
+                None
+            }
+        });
+        let mut kind = ErrorKind::Other;
+        for cause_kind in kinds {
+            kind = cause_kind;
+            if kind == ErrorKind::Timeout || kind == ErrorKind::Limit {
+                break;
+            }
+        }
+        kind
+    }
+
+    fn of_io(err: &std::io::Error) -> Self {
+        #[cfg(feature = "aswasm")]
+        {
+            use ya_runtime_aswasm::runtime::IoError;
+            if IoError::classify(err) == IoError::LimitExceeded {
+                return ErrorKind::Limit;
+            }
+        }
+        match err.kind() {
+            std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            _ => ErrorKind::Io,
+        }
+    }
+
+    /// Kind of a WASI runtime error, its causes may refine it.
+    #[cfg(feature = "wasi")]
+    fn of_wasi(err: &ya_runtime_wasi::Error) -> Option<Self> {
+        use ya_runtime_wasi::Error;
+        Some(match err {
+            Error::ManifestInvalid(_)
+            | Error::EntrypointNotFound(_)
+            | Error::NetworkRejected(_) => ErrorKind::Manifest,
+            Error::ImageIo { .. } | Error::MountRejected(_) => ErrorKind::Io,
+            Error::Compile { .. } | Error::Link { .. } => ErrorKind::Compile,
+            Error::Trap { .. } => ErrorKind::Trap,
+            Error::LimitExceeded { source, .. }
+                if source.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                ErrorKind::Timeout
+            }
+            Error::LimitExceeded { .. } => ErrorKind::Limit,
+            Error::Cancelled { .. } => ErrorKind::Other,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Messages of the error and all its causes, outermost first.
+    pub chain: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub status: Status,
+    pub exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+}
+
+impl From<anyhow::Result<Outcome>> for Report {
+    fn from(result: anyhow::Result<Outcome>) -> Self {
+        match result {
+            Ok(outcome) => Report {
+                status: Status::Ok,
+                exit_code: outcome.exit_code,
+                result: outcome.result,
+                error: None,
+            },
+            Err(err) => {
+                let exit_code = err
+                    .chain()
+                    .filter_map(|cause| cause.downcast_ref::<Trap>())
+                    .find_map(Trap::i32_exit_status)
+                    .filter(|&code| code != 0)
+                    .unwrap_or(1);
+                Report {
+                    status: Status::Error,
+                    exit_code,
+                    result: None,
+                    error: Some(ErrorReport {
+                        kind: ErrorKind::of(&err),
+                        message: format!("{:#}", err),
+                        chain: err.chain().map(ToString::to_string).collect(),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wasi"))]
+mod test {
+    use super::*;
+    use std::io;
+    use ya_runtime_wasi::{Error, NetworkUsage};
+
+    fn kind(err: Error) -> ErrorKind {
+        ErrorKind::of(&anyhow::Error::from(err).context("Failed to run"))
+    }
+
+    #[test]
+    fn test_limit_kinds() {
+        let limit = |kind| Error::LimitExceeded {
+            network: NetworkUsage::default(),
+            source: io::Error::new(kind, "limit exceeded"),
+        };
+        assert_eq!(kind(limit(io::ErrorKind::TimedOut)), ErrorKind::Timeout);
+        assert_eq!(kind(limit(io::ErrorKind::Other)), ErrorKind::Limit);
+        let cancelled = Error::Cancelled {
+            network: NetworkUsage::default(),
+        };
+        assert_eq!(kind(cancelled), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_network_rejected_kind() {
+        let err = Error::NetworkRejected("Network target [example.com:80] is not allowed.".into());
+        assert_eq!(kind(err), ErrorKind::Manifest);
+    }
+}
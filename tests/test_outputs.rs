@@ -12,11 +12,22 @@ fn exe_runtime(
     work_dir: &Path,
     command: &str,
     args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+) -> anyhow::Result<process::Output> {
+    exe_runtime_with(NO_ARGS, package, work_dir, command, args)
+}
+
+fn exe_runtime_with(
+    options: &[&str],
+    package: &str,
+    work_dir: &Path,
+    command: &str,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
 ) -> anyhow::Result<process::Output> {
     let app = env!("CARGO_BIN_EXE_ya-runtime-wasi");
     let output = process::Command::new(app)
         .stderr(process::Stdio::piped())
         .stdout(process::Stdio::piped())
+        .args(options)
         .args(&["--task-package", package])
         .arg("--workdir")
         .arg(work_dir)
@@ -79,3 +90,61 @@ fn test_outputs() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+fn json_report(output: &process::Output) -> serde_json::Value {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let last_line = stdout.lines().last().unwrap_or_default();
+    serde_json::from_str(last_line).unwrap_or_else(|e| {
+        panic!(
+            "expected json report as last line: {}\nstdout: [{}]\nstderr: [{}]",
+            e,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        )
+    })
+}
+
+#[test]
+fn test_json_outputs() -> anyhow::Result<()> {
+    let package = "tests/trusted-voting-mgr-66d7ce8208f4da9d7cbd.ywasi";
+    let dir = tempdir()?;
+    let json_args = |command: &str, args: &[&str]| {
+        exe_runtime_with(&["--output", "json"], package, dir.path(), command, args)
+    };
+
+    let output = json_args("deploy", &[])?;
+    let report = json_report(&output);
+    assert_eq!(report["status"], "ok");
+    assert_eq!(report["exitCode"], 0);
+    assert!(report["result"].is_object());
+    assert!(output.status.success());
+
+    let report = json_report(&json_args("start", &[])?);
+    assert_eq!(report["status"], "ok");
+    assert!(report.get("result").is_none());
+
+    let output = json_args("run", &["-e", "no-such-entrypoint"])?;
+    let report = json_report(&output);
+    assert_eq!(report["status"], "error");
+    assert_eq!(report["exitCode"], 1);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(report["error"]["kind"], "manifest");
+    assert!(report["error"]["message"].is_string());
+    assert!(!report["error"]["chain"].as_array().unwrap().is_empty());
+
+    let config = dir.path().join("timeout.toml");
+    std::fs::write(&config, "[wasi]\ntimeout-secs = 0\n")?;
+    let config = config.to_string_lossy();
+    let output = exe_runtime_with(
+        &["--output", "json", "--config", &config],
+        package,
+        dir.path(),
+        "run",
+        &["-e", "trusted-voting-mgr", "--", "debug"],
+    )?;
+    let report = json_report(&output);
+    assert_eq!(report["status"], "error");
+    assert_eq!(report["error"]["kind"], "timeout");
+
+    Ok(())
+}